@@ -0,0 +1,67 @@
+// Measures how many connections per second the server can accept
+// Run with: cargo run --release --example accept_rate [connections] [concurrency]
+use std::env;
+use std::net::{ IpAddr, Ipv4Addr };
+use std::time::Instant;
+
+use async_std::io::prelude::*;
+use async_std::io::Result;
+use async_std::net::TcpStream;
+use async_std::task;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use sync_tokens_example::config::ServerConfig;
+use sync_tokens_example::server::run_server;
+use sync_tokens_example::stream::ConnectionStream;
+use sync_tokens_example::timeouts::ConnectionTimeouts;
+
+#[async_std::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+    let connections: usize = args.next().map(|arg| arg.parse().unwrap()).unwrap_or(10_000);
+    let concurrency: usize = args.next().map(|arg| arg.parse().unwrap()).unwrap_or(32);
+
+    let config = ServerConfig::new()
+        .address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        .backlog(1024);
+
+    let mut server = run_server(config, close_immediately);
+    let local_addr = server.ready().await.unwrap()[0];
+
+    println!("Opening {} connections to {}, {} at a time", connections, local_addr, concurrency);
+
+    let start = Instant::now();
+
+    let clients: Vec<_> = (0..concurrency)
+        .map(|client| {
+            // Spread the connections evenly across the clients
+            let count = connections / concurrency + if client < connections % concurrency { 1 } else { 0 };
+
+            task::spawn(async move {
+                for _ in 0..count {
+                    // The server closes the connection right after accepting it, so reaching EOF
+                    // means that the server accepted the connection
+                    let mut stream = TcpStream::connect(local_addr).await.unwrap();
+                    let mut buf = [0u8; 1];
+                    while stream.read(&mut buf).await.unwrap() > 0 {}
+                }
+            })
+        })
+        .collect();
+
+    for client in clients {
+        client.await;
+    }
+
+    let elapsed = start.elapsed();
+
+    println!("Accepted {} connections in {:?}: {:.0} accepts per second", connections, elapsed, connections as f64 / elapsed.as_secs_f64());
+
+    server.shutdown();
+    server.join().await;
+}
+
+async fn close_immediately(_stream: ConnectionStream, _cancelable: Cancelable, _timeouts: ConnectionTimeouts) -> Result<()> {
+    Ok(())
+}
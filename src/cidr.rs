@@ -0,0 +1,156 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{ Error, ErrorKind };
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+// A range of IP addresses, like 10.0.0.0/8 or fd00::/8
+// A single address without a prefix length, like 192.168.1.10, matches only that address
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8
+}
+
+impl Cidr {
+    // Returns an error if prefix_len is longer than the address
+    pub fn new(network: IpAddr, prefix_len: u8) -> Result<Cidr, Error> {
+        let max_prefix_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128
+        };
+
+        if prefix_len > max_prefix_len {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Prefix length {} is too long for {}", prefix_len, network)));
+        }
+
+        Ok(Cidr {
+            network,
+            prefix_len
+        })
+    }
+
+    // Whether address is in this range
+    // IPv4 addresses that arrive on a dual-stack listener as IPv4-mapped IPv6 addresses, like
+    // ::ffff:10.0.0.1, match IPv4 ranges
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _ => false
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Cidr, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid address range '{}'", s));
+
+        match s.split_once('/') {
+            Some((network, prefix_len)) => {
+                let network = network.parse::<IpAddr>().map_err(|_| invalid())?;
+                let prefix_len = prefix_len.parse::<u8>().map_err(|_| invalid())?;
+                Cidr::new(network, prefix_len)
+            },
+            None => {
+                let network = s.parse::<IpAddr>().map_err(|_| invalid())?;
+                let prefix_len = if network.is_ipv4() { 32 } else { 128 };
+                Cidr::new(network, prefix_len)
+            }
+        }
+    }
+}
+
+// Used by serde to read ranges from the configuration file
+impl TryFrom<String> for Cidr {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Cidr, Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+// Shorthands for writing addresses and ranges in tests, shared by every module's tests
+#[cfg(test)]
+pub(crate) mod test_helpers {
+    use std::net::IpAddr;
+
+    use super::Cidr;
+
+    pub(crate) fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    pub(crate) fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_helpers::{ cidr, ip };
+
+    #[test]
+    fn parses_ranges_and_single_addresses() {
+        assert_eq!(cidr("10.0.0.0/8"), Cidr::new(ip("10.0.0.0"), 8).unwrap());
+        assert_eq!(cidr("fd00::/8"), Cidr::new(ip("fd00::"), 8).unwrap());
+        assert_eq!(cidr("192.168.1.10"), Cidr::new(ip("192.168.1.10"), 32).unwrap());
+        assert_eq!(cidr("::1"), Cidr::new(ip("::1"), 128).unwrap());
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for s in ["", "10.0.0.0/", "10.0.0.0/33", "::/129", "10.0.0/8", "10.0.0.0/x", "host/8"].iter() {
+            assert_eq!(s.parse::<Cidr>().unwrap_err().kind(), ErrorKind::InvalidInput, "{}", s);
+        }
+    }
+
+    #[test]
+    fn contains_addresses_in_the_range() {
+        let range = cidr("10.1.0.0/16");
+
+        assert!(range.contains(ip("10.1.0.0")));
+        assert!(range.contains(ip("10.1.255.255")));
+        assert!(!range.contains(ip("10.2.0.0")));
+        assert!(!range.contains(ip("::1")));
+
+        // The host bits of the range don't matter
+        assert!(cidr("10.1.2.3/16").contains(ip("10.1.200.1")));
+
+        assert!(cidr("fd00::/8").contains(ip("fd12:3456::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn prefix_lengths_at_the_edges() {
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(cidr("192.168.1.10").contains(ip("192.168.1.10")));
+        assert!(!cidr("192.168.1.10").contains(ip("192.168.1.11")));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_ipv4_ranges() {
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.0.0.1")));
+    }
+}
@@ -0,0 +1,369 @@
+use std::net::{ IpAddr, Ipv4Addr, SocketAddr };
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cidr::Cidr;
+
+// Settings for run_server
+// The defaults listen on an ephemeral port on all IPv4 interfaces, and cancel in-flight
+// connections immediately when the server is canceled
+// Listener settings only take effect when the server starts, the rest can be changed while the
+// server is running with ServerHandle::reload
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub(crate) addresses: Vec<IpAddr>,
+    pub(crate) port: u16,
+    pub(crate) socket_addrs: Vec<SocketAddr>,
+    pub(crate) backlog: i32,
+    pub(crate) dual_stack: Option<bool>,
+    pub(crate) drain_timeout: Option<Duration>,
+    pub(crate) startup_timeout: Option<Duration>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_connections_per_ip: Option<usize>,
+    pub(crate) connection_rate_per_ip: Option<RateLimit>,
+    pub(crate) exempt_networks: Vec<Cidr>,
+    pub(crate) allowed_networks: Vec<Cidr>,
+    pub(crate) denied_networks: Vec<Cidr>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) max_connection_lifetime: Option<Duration>,
+    pub(crate) tls: Option<TlsSettings>,
+    pub(crate) tls_handshake_timeout: Option<Duration>
+}
+
+// The settings that ServerHandle::reload can change while the server is running
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ReloadableConfig {
+    pub(crate) drain_timeout: Option<Duration>,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_connections_per_ip: Option<usize>,
+    pub(crate) connection_rate_per_ip: Option<RateLimit>,
+    pub(crate) exempt_networks: Vec<Cidr>,
+    pub(crate) allowed_networks: Vec<Cidr>,
+    pub(crate) denied_networks: Vec<Cidr>,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) max_connection_lifetime: Option<Duration>,
+    pub(crate) tls_handshake_timeout: Option<Duration>
+}
+
+// PEM files with the server's certificate chain and private key
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TlsSettings {
+    pub(crate) cert_path: PathBuf,
+    pub(crate) key_path: PathBuf
+}
+
+impl ReloadableConfig {
+    // Whether the allowed and denied ranges let address connect
+    pub(crate) fn permits(&self, address: IpAddr) -> bool {
+        if self.denied_networks.iter().any(|network| network.contains(address)) {
+            return false;
+        }
+
+        self.allowed_networks.is_empty() || self.allowed_networks.iter().any(|network| network.contains(address))
+    }
+}
+
+// A token bucket: each peer can open burst connections at once, and then per_second more
+// connections each second
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct RateLimit {
+    pub(crate) per_second: f64,
+    pub(crate) burst: u32
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addresses: Vec::new(),
+            port: 0,
+            socket_addrs: Vec::new(),
+            backlog: 128,
+            dual_stack: None,
+            drain_timeout: None,
+            startup_timeout: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            connection_rate_per_ip: None,
+            exempt_networks: Vec::new(),
+            allowed_networks: Vec::new(),
+            denied_networks: Vec::new(),
+            idle_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            max_connection_lifetime: None,
+            tls: None,
+            tls_handshake_timeout: None
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds an address to listen on, using the port set with port()
+    // Call this once for each interface; for example, once for Ipv4Addr::UNSPECIFIED and once
+    // for Ipv6Addr::UNSPECIFIED. (Binding both on the same port requires dual_stack(false).)
+    pub fn address(mut self, address: IpAddr) -> Self {
+        self.addresses.push(address);
+        self
+    }
+
+    // The port used for addresses added with address(), 0 lets the operating system pick an
+    // ephemeral port for each one
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    // Adds an address and port to listen on, for example a loopback-only admin port
+    pub fn listen(mut self, socket_addr: SocketAddr) -> Self {
+        self.socket_addrs.push(socket_addr);
+        self
+    }
+
+    // Removes the addresses added with address() and listen(), for example to replace
+    // addresses from a configuration file with ones from the command line
+    pub fn clear_addresses(mut self) -> Self {
+        self.addresses.clear();
+        self.socket_addrs.clear();
+        self
+    }
+
+    // How many pending connections the operating system queues before they are accepted
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    // When listening on an IPv6 address, whether IPv4 clients can connect to the same socket
+    // If this isn't set, the operating system's default is used
+    pub fn dual_stack(mut self, dual_stack: bool) -> Self {
+        self.dual_stack = Some(dual_stack);
+        self
+    }
+
+    // How long in-flight connections have to finish when the server is canceled
+    // If this isn't set, or is zero, in-flight connections are canceled immediately
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    // How long the server has to start listening
+    // If it takes longer, the server stops and the CompletionToken returns a TimedOut error
+    pub fn startup_timeout(mut self, startup_timeout: Duration) -> Self {
+        self.startup_timeout = Some(startup_timeout);
+        self
+    }
+
+    // How long a connection can wait for the client to start sending its next message or
+    // request
+    // This and the other connection timeouts are enforced by handlers that use
+    // ConnectionTimeouts, which all of the built-in handlers do. Changes apply to connections
+    // accepted afterwards.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    // How long a single read can take once the client has started sending a message
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = Some(read_timeout);
+        self
+    }
+
+    // How long a single write to the client can take
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = Some(write_timeout);
+        self
+    }
+
+    // How long a connection can stay open in total, whether or not it's busy
+    // Unlike the other connection timeouts, this is also enforced for handlers that don't use
+    // ConnectionTimeouts
+    pub fn max_connection_lifetime(mut self, max_connection_lifetime: Duration) -> Self {
+        self.max_connection_lifetime = Some(max_connection_lifetime);
+        self
+    }
+
+    // Encrypts every connection with TLS, using the certificate chain and private key in these
+    // PEM files
+    // The files are read when the server starts; if they can't be read, the server fails to
+    // start. Handlers receive the decrypted ConnectionStream once the handshake finishes.
+    pub fn tls<P: Into<PathBuf>>(mut self, cert_path: P, key_path: P) -> Self {
+        self.tls = Some(TlsSettings {
+            cert_path: cert_path.into(),
+            key_path: key_path.into()
+        });
+        self
+    }
+
+    // How long a client has to finish the TLS handshake, 10 seconds if this isn't set
+    pub fn tls_handshake_timeout(mut self, tls_handshake_timeout: Duration) -> Self {
+        self.tls_handshake_timeout = Some(tls_handshake_timeout);
+        self
+    }
+
+    // The most connections that can be in flight at once
+    // When the limit is reached, the server stops accepting until a connection ends. Each
+    // listener holds on to the client that it accepted last until then, and other new clients
+    // wait in the listen backlog. If this isn't set, there is no limit.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    // The most connections that a single IP address can have in flight at once
+    // Connections over the limit are closed as soon as they are accepted. If this isn't set,
+    // there is no limit.
+    pub fn max_connections_per_ip(mut self, max_connections_per_ip: usize) -> Self {
+        self.max_connections_per_ip = Some(max_connections_per_ip);
+        self
+    }
+
+    // How often a single IP address can connect: burst connections at once, and then
+    // per_second more each second
+    // Connections over the limit are closed as soon as they are accepted. If this isn't set,
+    // there is no limit.
+    // Panics if per_second isn't a positive number, or if burst is 0, since either would turn
+    // the limit off or turn every peer away
+    pub fn connection_rate_per_ip(mut self, per_second: f64, burst: u32) -> Self {
+        assert!(per_second.is_finite() && per_second > 0.0, "connection_rate_per_ip needs a positive rate, not {}", per_second);
+        assert!(burst > 0, "connection_rate_per_ip needs a burst of at least 1");
+
+        self.connection_rate_per_ip = Some(RateLimit { per_second, burst });
+        self
+    }
+
+    // Adds a range of addresses that max_connections_per_ip and connection_rate_per_ip don't
+    // apply to, for example internal load balancers or monitoring
+    pub fn exempt_network(mut self, network: Cidr) -> Self {
+        self.exempt_networks.push(network);
+        self
+    }
+
+    // Adds a range of addresses that can connect
+    // Once any range is allowed, clients outside of every allowed range are disconnected as
+    // soon as they are accepted. If no ranges are allowed, every client can connect.
+    pub fn allow_network(mut self, network: Cidr) -> Self {
+        self.allowed_networks.push(network);
+        self
+    }
+
+    // Adds a range of addresses that can't connect, even if they are in an allowed range
+    // Clients in the range are disconnected as soon as they are accepted
+    pub fn deny_network(mut self, network: Cidr) -> Self {
+        self.denied_networks.push(network);
+        self
+    }
+
+    // Every address and port to listen on, one listener is bound for each
+    // If no addresses were added, the server listens on all IPv4 interfaces
+    pub fn socket_addrs(&self) -> Vec<SocketAddr> {
+        let mut socket_addrs: Vec<SocketAddr> = self.addresses
+            .iter()
+            .map(|address| SocketAddr::new(*address, self.port))
+            .chain(self.socket_addrs.iter().cloned())
+            .collect();
+
+        if socket_addrs.is_empty() {
+            socket_addrs.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), self.port));
+        }
+
+        socket_addrs
+    }
+
+    pub(crate) fn reloadable(&self) -> ReloadableConfig {
+        ReloadableConfig {
+            drain_timeout: self.drain_timeout,
+            max_connections: self.max_connections,
+            max_connections_per_ip: self.max_connections_per_ip,
+            connection_rate_per_ip: self.connection_rate_per_ip,
+            exempt_networks: self.exempt_networks.clone(),
+            allowed_networks: self.allowed_networks.clone(),
+            denied_networks: self.denied_networks.clone(),
+            idle_timeout: self.idle_timeout,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            max_connection_lifetime: self.max_connection_lifetime,
+            tls_handshake_timeout: self.tls_handshake_timeout
+        }
+    }
+
+    // Whether other binds the same listeners, in which case the server can switch to other
+    // without restarting
+    pub(crate) fn same_listeners(&self, other: &ServerConfig) -> bool {
+        self.socket_addrs() == other.socket_addrs()
+            && self.backlog == other.backlog
+            && self.dual_stack == other.dual_stack
+            && self.tls == other.tls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cidr::test_helpers::{ cidr, ip };
+
+    use super::*;
+
+    #[test]
+    fn permits_everyone_by_default() {
+        let config = ServerConfig::new().reloadable();
+
+        assert!(config.permits(ip("203.0.113.9")));
+        assert!(config.permits(ip("::1")));
+    }
+
+    #[test]
+    fn permits_only_allowed_networks() {
+        let config = ServerConfig::new()
+            .allow_network(cidr("10.0.0.0/8"))
+            .allow_network(cidr("::1"))
+            .reloadable();
+
+        assert!(config.permits(ip("10.1.2.3")));
+        assert!(config.permits(ip("::1")));
+        assert!(!config.permits(ip("192.168.1.1")));
+    }
+
+    #[test]
+    fn denied_networks_win() {
+        let config = ServerConfig::new()
+            .allow_network(cidr("10.0.0.0/8"))
+            .deny_network(cidr("10.0.0.0/24"))
+            .reloadable();
+
+        assert!(config.permits(ip("10.0.1.1")));
+        assert!(!config.permits(ip("10.0.0.1")));
+
+        // Denying without allowing lets everyone else in
+        let config = ServerConfig::new().deny_network(cidr("192.168.0.0/16")).reloadable();
+
+        assert!(!config.permits(ip("::ffff:192.168.1.1")));
+        assert!(config.permits(ip("10.0.0.1")));
+    }
+
+    #[test]
+    #[should_panic(expected = "positive rate")]
+    fn rate_must_be_positive() {
+        ServerConfig::new().connection_rate_per_ip(0.0, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive rate")]
+    fn rate_cant_be_nan() {
+        ServerConfig::new().connection_rate_per_ip(f64::NAN, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "burst of at least 1")]
+    fn burst_cant_be_zero() {
+        ServerConfig::new().connection_rate_per_ip(1.0, 0);
+    }
+}
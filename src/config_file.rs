@@ -0,0 +1,312 @@
+use std::io::{ Error, ErrorKind };
+use std::net::{ IpAddr, SocketAddr };
+use std::num::{ NonZeroU32, NonZeroUsize };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+use async_std::fs;
+use async_std::io::Result;
+
+use serde::Deserialize;
+
+use crate::cidr::Cidr;
+use crate::config::ServerConfig;
+
+// Server settings read from a TOML file, see server.toml for an example
+// Every setting is optional, settings that aren't in the file are left as they are
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    listeners: Listeners,
+    timeouts: Timeouts,
+    limits: Limits,
+    access: Access,
+    tls: Tls
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Listeners {
+    addresses: Option<Vec<IpAddr>>,
+    port: Option<u16>,
+    listen: Option<Vec<SocketAddr>>,
+    backlog: Option<i32>,
+    dual_stack: Option<bool>
+}
+
+// In seconds
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Timeouts {
+    startup: Option<f64>,
+    shutdown: Option<f64>,
+    idle: Option<f64>,
+    read: Option<f64>,
+    write: Option<f64>,
+    lifetime: Option<f64>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Limits {
+    max_connections: Option<NonZeroUsize>,
+    max_connections_per_ip: Option<NonZeroUsize>,
+    // Connections per second
+    connection_rate_per_ip: Option<f64>,
+    connection_burst_per_ip: Option<NonZeroU32>,
+    exempt: Option<Vec<Cidr>>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Access {
+    allow: Option<Vec<Cidr>>,
+    deny: Option<Vec<Cidr>>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Tls {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    // In seconds
+    handshake_timeout: Option<f64>
+}
+
+impl ConfigFile {
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<ConfigFile> {
+        let contents = fs::read_to_string(path.as_ref()).await?;
+        toml::from_str(&contents).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    }
+
+    // Applies the settings in the file on top of config
+    // If the file lists any addresses, they replace the addresses already in config
+    pub fn apply(&self, mut config: ServerConfig) -> Result<ServerConfig> {
+        let listeners = &self.listeners;
+
+        if listeners.addresses.is_some() || listeners.listen.is_some() {
+            config = config.clear_addresses();
+        }
+
+        for address in listeners.addresses.iter().flatten() {
+            config = config.address(*address);
+        }
+
+        for socket_addr in listeners.listen.iter().flatten() {
+            config = config.listen(*socket_addr);
+        }
+
+        if let Some(port) = listeners.port {
+            config = config.port(port);
+        }
+
+        if let Some(backlog) = listeners.backlog {
+            config = config.backlog(backlog);
+        }
+
+        if let Some(dual_stack) = listeners.dual_stack {
+            config = config.dual_stack(dual_stack);
+        }
+
+        if let Some(startup) = self.timeouts.startup {
+            config = config.startup_timeout(seconds("timeouts.startup", startup)?);
+        }
+
+        if let Some(shutdown) = self.timeouts.shutdown {
+            config = config.drain_timeout(seconds("timeouts.shutdown", shutdown)?);
+        }
+
+        if let Some(idle) = self.timeouts.idle {
+            config = config.idle_timeout(seconds("timeouts.idle", idle)?);
+        }
+
+        if let Some(read) = self.timeouts.read {
+            config = config.read_timeout(seconds("timeouts.read", read)?);
+        }
+
+        if let Some(write) = self.timeouts.write {
+            config = config.write_timeout(seconds("timeouts.write", write)?);
+        }
+
+        if let Some(lifetime) = self.timeouts.lifetime {
+            config = config.max_connection_lifetime(seconds("timeouts.lifetime", lifetime)?);
+        }
+
+        if let Some(max_connections) = self.limits.max_connections {
+            config = config.max_connections(max_connections.get());
+        }
+
+        if let Some(max_connections_per_ip) = self.limits.max_connections_per_ip {
+            config = config.max_connections_per_ip(max_connections_per_ip.get());
+        }
+
+        if let Some(rate) = self.limits.connection_rate_per_ip {
+            if !(rate.is_finite() && rate > 0.0) {
+                return Err(Error::new(ErrorKind::InvalidData, "limits.connection_rate_per_ip must be a positive number"));
+            }
+
+            // By default, peers can connect as often as the rate allows in a single second
+            let burst = match self.limits.connection_burst_per_ip {
+                Some(burst) => burst.get(),
+                None => rate.ceil() as u32
+            };
+
+            config = config.connection_rate_per_ip(rate, burst);
+        }
+
+        for network in self.limits.exempt.iter().flatten() {
+            config = config.exempt_network(*network);
+        }
+
+        for network in self.access.allow.iter().flatten() {
+            config = config.allow_network(*network);
+        }
+
+        for network in self.access.deny.iter().flatten() {
+            config = config.deny_network(*network);
+        }
+
+        match (&self.tls.cert, &self.tls.key) {
+            (Some(cert), Some(key)) => config = config.tls(cert, key),
+            (None, None) => {},
+            _ => return Err(Error::new(ErrorKind::InvalidData, "tls.cert and tls.key must be set together"))
+        }
+
+        if let Some(handshake_timeout) = self.tls.handshake_timeout {
+            config = config.tls_handshake_timeout(seconds("tls.handshake_timeout", handshake_timeout)?);
+        }
+
+        Ok(config)
+    }
+}
+
+fn seconds(name: &str, seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} must be a positive number of seconds", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::cidr::test_helpers::{ cidr, ip };
+    use crate::config::{ RateLimit, TlsSettings };
+
+    use super::*;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn parse(toml: &str) -> ConfigFile {
+        toml::from_str(toml).unwrap()
+    }
+
+    // Applies the file on top of a config that listens on localhost
+    fn apply(toml: &str) -> Result<ServerConfig> {
+        parse(toml).apply(ServerConfig::new().address(LOCALHOST).port(8080))
+    }
+
+    fn error(toml: &str) -> String {
+        apply(toml).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn the_example_file_parses() {
+        assert!(parse(include_str!("../server.toml")).apply(ServerConfig::new()).is_ok());
+    }
+
+    #[test]
+    fn an_empty_file_changes_nothing() {
+        let config = apply("").unwrap();
+
+        assert_eq!(config.addresses, vec![LOCALHOST]);
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.drain_timeout, None);
+        assert_eq!(config.tls, None);
+    }
+
+    #[test]
+    fn addresses_replace_the_existing_addresses() {
+        let config = apply("[listeners]\naddresses = [\"::1\"]\nport = 0").unwrap();
+
+        assert_eq!(config.addresses, vec![ip("::1")]);
+        assert!(config.socket_addrs.is_empty());
+        assert_eq!(config.port, 0);
+    }
+
+    #[test]
+    fn listen_replaces_the_existing_addresses() {
+        let config = apply("[listeners]\nlisten = [\"127.0.0.1:9090\"]").unwrap();
+
+        assert!(config.addresses.is_empty());
+        assert_eq!(config.socket_addrs(), vec!["127.0.0.1:9090".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn timeouts_are_in_seconds() {
+        let config = apply("[timeouts]\nshutdown = 1.5\nidle = 60\nlifetime = 0").unwrap();
+
+        assert_eq!(config.drain_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(config.max_connection_lifetime, Some(Duration::from_secs(0)));
+        assert_eq!(config.read_timeout, None);
+    }
+
+    #[test]
+    fn timeouts_cant_be_negative() {
+        assert_eq!(error("[timeouts]\nidle = -1"), "timeouts.idle must be a positive number of seconds");
+        assert_eq!(error("[timeouts]\nshutdown = nan"), "timeouts.shutdown must be a positive number of seconds");
+        assert_eq!(error("[tls]\nhandshake_timeout = -0.5"), "tls.handshake_timeout must be a positive number of seconds");
+    }
+
+    #[test]
+    fn burst_defaults_to_the_rate() {
+        let config = apply("[limits]\nconnection_rate_per_ip = 2.5").unwrap();
+        assert_eq!(config.connection_rate_per_ip, Some(RateLimit { per_second: 2.5, burst: 3 }));
+
+        let config = apply("[limits]\nconnection_rate_per_ip = 2.5\nconnection_burst_per_ip = 10").unwrap();
+        assert_eq!(config.connection_rate_per_ip, Some(RateLimit { per_second: 2.5, burst: 10 }));
+    }
+
+    #[test]
+    fn rate_must_be_positive() {
+        for rate in ["0", "-1", "nan", "inf"].iter() {
+            let toml = format!("[limits]\nconnection_rate_per_ip = {}", rate);
+            assert_eq!(error(&toml), "limits.connection_rate_per_ip must be a positive number", "{}", rate);
+        }
+    }
+
+    #[test]
+    fn limits_cant_be_zero() {
+        assert!(toml::from_str::<ConfigFile>("[limits]\nmax_connections = 0").is_err());
+        assert!(toml::from_str::<ConfigFile>("[limits]\nconnection_burst_per_ip = 0").is_err());
+    }
+
+    #[test]
+    fn tls_needs_both_files() {
+        let config = apply("[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"").unwrap();
+
+        assert_eq!(config.tls, Some(TlsSettings {
+            cert_path: PathBuf::from("cert.pem"),
+            key_path: PathBuf::from("key.pem")
+        }));
+
+        assert_eq!(error("[tls]\ncert = \"cert.pem\""), "tls.cert and tls.key must be set together");
+        assert_eq!(error("[tls]\nkey = \"key.pem\""), "tls.cert and tls.key must be set together");
+    }
+
+    #[test]
+    fn networks_are_added() {
+        let config = apply("[limits]\nexempt = [\"10.0.0.0/8\"]\n[access]\nallow = [\"10.0.0.0/8\", \"::1\"]\ndeny = [\"10.0.0.1\"]").unwrap();
+
+        assert_eq!(config.exempt_networks.len(), 1);
+        assert_eq!(config.allowed_networks.len(), 2);
+        assert_eq!(config.denied_networks, vec![cidr("10.0.0.1/32")]);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<ConfigFile>("[limits]\nmax_conections = 5").is_err());
+        assert!(toml::from_str::<ConfigFile>("[listener]\nport = 80").is_err());
+        assert!(toml::from_str::<ConfigFile>("[access]\nallow = [\"10.0.0.0/40\"]").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{ Error, ErrorKind };
+use std::pin::Pin;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+
+use async_std::future;
+use async_std::io::Result;
+
+use sync_tokens::cancelation_token::{ Cancelable, CancelationToken };
+use sync_tokens::completion_token::{ Completable, CompletionToken };
+
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+
+// The future returned when a handler starts working on a connection
+pub type ConnectionFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+// Handles a single accepted connection
+// run_server spawns a new task for each accepted connection and runs the handler's future on it
+// The Cancelable is canceled when the server is stopped, handlers should use it to wrap
+// anything that can wait for a long time, like reads from the client
+// The ConnectionTimeouts are the connection's deadlines, handlers wrap each read and write in
+// them the same way
+pub trait ConnectionHandler: Send + Sync + 'static {
+    fn handle(&self, stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> ConnectionFuture;
+
+    // Called once the server is listening, right after the CompletionToken is completed
+    // Not called if the server fails to start
+    fn started(&self) {}
+
+    // Called when the server is canceled, after it stops accepting and before in-flight
+    // connections are drained
+    // Handlers can use this to close connections that are idle, like HTTP keep-alive
+    // connections, instead of waiting for the drain timeout to cancel them
+    fn shutdown_started(&self) {}
+}
+
+// The error that handlers return when their Cancelable is canceled
+pub fn server_terminated() -> Error {
+    Error::new(ErrorKind::Interrupted, "Server terminated")
+}
+
+// Allows passing a closure (or async fn) as the handler
+impl<F, Fut> ConnectionHandler for F
+where
+    F: Fn(ConnectionStream, Cancelable, ConnectionTimeouts) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    fn handle(&self, stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> ConnectionFuture {
+        Box::pin(self(stream, cancelable, timeouts))
+    }
+}
+
+// Keeps a CancelationToken for each in-flight connection, so that canceling the server
+// cancels every connection that it spawned
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionTracker {
+    state: Arc<Mutex<TrackerState>>
+}
+
+#[derive(Default)]
+struct TrackerState {
+    next_id: u64,
+    canceled: bool,
+    active: usize,
+    cancelation_tokens: HashMap<u64, CancelationToken>,
+    // Set while draining, completed when the last connection ends
+    drained_completable: Option<Completable<()>>,
+    // Completed when a connection ends, so that anything waiting for a free slot checks again
+    // Keyed by SlotWaiter::id, so that a waiter that gives up can remove its own entry
+    slot_completables: HashMap<u64, Completable<()>>
+}
+
+// How many connections finished on their own during a graceful shutdown, and how many had to
+// be canceled because they were still running when the drain timeout expired
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShutdownStats {
+    pub drained: usize,
+    pub aborted: usize
+}
+
+// A reserved place for a connection that was accepted but isn't registered yet, counted as
+// in-flight
+// Works like a semaphore permit: if it's dropped without being registered, the slot is freed
+pub(crate) struct ConnectionSlot {
+    // Only None after register
+    state: Option<Arc<Mutex<TrackerState>>>
+}
+
+// Returned from try_reserve when the limit is reached, waits for a connection to end
+// Dropping it, for example when the wait times out, removes it from the tracker
+pub(crate) struct SlotWaiter {
+    id: u64,
+    // Only None once wait has taken it
+    slot_token: Option<CompletionToken<()>>,
+    state: Arc<Mutex<TrackerState>>
+}
+
+// Removes a connection's CancelationToken from the tracker when the connection ends
+pub(crate) struct ConnectionGuard {
+    id: u64,
+    state: Arc<Mutex<TrackerState>>
+}
+
+impl ConnectionTracker {
+    // Registers a new connection
+    // The returned Cancelable is canceled when cancel_all is called. If cancel_all was already
+    // called, it is canceled immediately.
+    pub fn register(&self) -> (ConnectionGuard, Cancelable) {
+        self.state.lock().unwrap().active += 1;
+
+        let slot = ConnectionSlot {
+            state: Some(self.state.clone())
+        };
+
+        slot.register()
+    }
+
+    // Reserves a slot for a connection, unless max_connections are already in flight
+    // When the limit is reached, returns a SlotWaiter that finishes waiting when a connection
+    // ends, after which the caller should try again
+    pub fn try_reserve(&self, max_connections: Option<usize>) -> std::result::Result<ConnectionSlot, SlotWaiter> {
+        let mut state = self.state.lock().unwrap();
+
+        match max_connections {
+            Some(max_connections) if state.active >= max_connections => {
+                let id = state.next_id;
+                state.next_id += 1;
+
+                let (slot_token, slot_completable) = CompletionToken::new();
+                state.slot_completables.insert(id, slot_completable);
+
+                Err(SlotWaiter {
+                    id,
+                    slot_token: Some(slot_token),
+                    state: self.state.clone()
+                })
+            },
+            _ => {
+                state.active += 1;
+
+                Ok(ConnectionSlot {
+                    state: Some(self.state.clone())
+                })
+            }
+        }
+    }
+
+    // Cancels all in-flight connections, and any connection registered afterwards
+    pub fn cancel_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.cancel_all();
+    }
+
+    // Waits up to timeout for all in-flight connections to finish, and then cancels the ones
+    // that are still running
+    pub async fn drain(&self, timeout: Duration) -> ShutdownStats {
+        let (active, drained_token) = {
+            let mut state = self.state.lock().unwrap();
+
+            if state.active == 0 {
+                state.cancel_all();
+                return ShutdownStats::default();
+            }
+
+            // The last ConnectionGuard to drop completes drained_token
+            let (drained_token, drained_completable) = CompletionToken::new();
+            state.drained_completable = Some(drained_completable);
+
+            (state.active, drained_token)
+        };
+
+        // The result is ignored because the remaining connections are counted below
+        let _ = future::timeout(timeout, drained_token).await;
+
+        let mut state = self.state.lock().unwrap();
+        let aborted = state.active;
+        state.cancel_all();
+
+        ShutdownStats {
+            drained: active - aborted,
+            aborted
+        }
+    }
+}
+
+impl ConnectionSlot {
+    // Registers the connection that this slot was reserved for, see ConnectionTracker::register
+    pub fn register(mut self) -> (ConnectionGuard, Cancelable) {
+        let state_arc = self.state.take().unwrap();
+        let (cancelation_token, cancelable) = CancelationToken::new();
+
+        let mut state = state_arc.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        if state.canceled {
+            cancelation_token.cancel();
+        } else {
+            state.cancelation_tokens.insert(id, cancelation_token);
+        }
+
+        drop(state);
+
+        let guard = ConnectionGuard {
+            id,
+            state: state_arc
+        };
+
+        (guard, cancelable)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            state.lock().unwrap().release();
+        }
+    }
+}
+
+impl SlotWaiter {
+    // Waits until a connection ends, or a reserved slot is freed
+    pub async fn wait(mut self) {
+        if let Some(slot_token) = self.slot_token.take() {
+            slot_token.await;
+        }
+    }
+}
+
+impl Drop for SlotWaiter {
+    fn drop(&mut self) {
+        // Already gone if a connection ended while waiting
+        self.state.lock().unwrap().slot_completables.remove(&self.id);
+    }
+}
+
+impl TrackerState {
+    fn cancel_all(&mut self) {
+        self.canceled = true;
+        self.drained_completable = None;
+
+        for (_, cancelation_token) in self.cancelation_tokens.drain() {
+            cancelation_token.cancel();
+        }
+    }
+
+    // Called when a connection ends, or a slot is freed without being used
+    fn release(&mut self) {
+        self.active -= 1;
+
+        for (_, slot_completable) in self.slot_completables.drain() {
+            slot_completable.complete(());
+        }
+
+        if self.active == 0 {
+            if let Some(drained_completable) = self.drained_completable.take() {
+                drained_completable.complete(());
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.cancelation_tokens.remove(&self.id);
+        state.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_std::{ future, task };
+
+    use super::*;
+
+    #[async_std::test]
+    async fn slot_waiters_are_removed_when_they_give_up() {
+        let tracker = ConnectionTracker::default();
+        let slot = tracker.try_reserve(Some(1)).ok().unwrap();
+
+        // Waiting over and over, like the accept loop does while the limit is reached
+        for _ in 0..10 {
+            let slot_waiter = tracker.try_reserve(Some(1)).err().unwrap();
+            assert!(future::timeout(Duration::from_millis(1), slot_waiter.wait()).await.is_err());
+        }
+
+        assert!(tracker.state.lock().unwrap().slot_completables.is_empty());
+
+        // Freeing the slot wakes a waiter
+        let slot_waiter = tracker.try_reserve(Some(1)).err().unwrap();
+        drop(slot);
+        future::timeout(Duration::from_secs(5), slot_waiter.wait()).await.unwrap();
+        assert!(tracker.try_reserve(Some(1)).is_ok());
+    }
+
+    #[async_std::test]
+    async fn drain_cancels_connections_that_dont_finish_in_time() {
+        let tracker = ConnectionTracker::default();
+        let (finishing_guard, _finishing_cancelable) = tracker.register();
+        let (straggler_guard, straggler_cancelable) = tracker.register();
+
+        // The first connection finishes partway through the drain, the straggler only stops
+        // once it's canceled
+        let finishing = task::spawn(async move {
+            task::sleep(Duration::from_millis(10)).await;
+            drop(finishing_guard);
+        });
+
+        let straggler = task::spawn(async move {
+            let canceled = straggler_cancelable.allow_cancel(future::pending::<bool>(), true).await;
+            drop(straggler_guard);
+            canceled
+        });
+
+        let stats = tracker.drain(Duration::from_millis(500)).await;
+        assert_eq!(stats, ShutdownStats { drained: 1, aborted: 1 });
+
+        finishing.await;
+        assert!(future::timeout(Duration::from_secs(5), straggler).await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn drain_returns_right_away_without_connections() {
+        let tracker = ConnectionTracker::default();
+
+        let stats = future::timeout(Duration::from_secs(5), tracker.drain(Duration::from_secs(60))).await.unwrap();
+        assert_eq!(stats, ShutdownStats::default());
+
+        // Connections registered afterwards are canceled immediately
+        let (_guard, cancelable) = tracker.register();
+        assert!(cancelable.allow_cancel(future::pending::<bool>(), true).await);
+    }
+}
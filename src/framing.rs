@@ -0,0 +1,298 @@
+use std::io::{ Error, ErrorKind };
+
+use async_std::io::{ BufReader, Result };
+use async_std::io::prelude::*;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use crate::connection::server_terminated;
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+
+// Frames larger than this are rejected, unless FrameCodec::max_frame_size is called
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+// How many bytes the length prefix takes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrefixWidth {
+    U8,
+    U16,
+    U32,
+    U64
+}
+
+// The byte order of the length prefix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endianness {
+    Big,
+    Little
+}
+
+// Settings for protocols where each message is sent as a length prefix followed by that many
+// bytes
+// The defaults are a big-endian u32 prefix, and frames of up to 16 MiB
+#[derive(Clone, Copy, Debug)]
+pub struct FrameCodec {
+    prefix_width: PrefixWidth,
+    endianness: Endianness,
+    max_frame_size: usize
+}
+
+// Reads and writes frames on an accepted ConnectionStream
+pub struct FramedStream {
+    reader: BufReader<ConnectionStream>,
+    writer: ConnectionStream,
+    codec: FrameCodec,
+    timeouts: ConnectionTimeouts
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec {
+            prefix_width: PrefixWidth::U32,
+            endianness: Endianness::Big,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE
+        }
+    }
+}
+
+impl PrefixWidth {
+    fn bytes(self) -> usize {
+        match self {
+            PrefixWidth::U8 => 1,
+            PrefixWidth::U16 => 2,
+            PrefixWidth::U32 => 4,
+            PrefixWidth::U64 => 8
+        }
+    }
+}
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn prefix_width(mut self, prefix_width: PrefixWidth) -> Self {
+        self.prefix_width = prefix_width;
+        self
+    }
+
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    // The largest frame that can be read or written, not counting the prefix
+    // Reading a larger frame fails before the frame is read, so a client can't use up memory
+    // by sending a huge prefix
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    // Reads and writes are raced against timeouts, like a handler's own reads and writes
+    pub fn framed(self, stream: ConnectionStream, timeouts: ConnectionTimeouts) -> FramedStream {
+        FramedStream {
+            reader: BufReader::new(stream.clone()),
+            writer: stream,
+            codec: self,
+            timeouts
+        }
+    }
+
+    fn encode_prefix(&self, len: usize) -> Result<Vec<u8>> {
+        let width = self.prefix_width.bytes();
+
+        if len > self.max_frame_size || (width < 8 && (len as u64) >> (width * 8) != 0) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("A frame of {} bytes is too large", len)));
+        }
+
+        let bytes = match self.endianness {
+            Endianness::Big => (len as u64).to_be_bytes()[8 - width..].to_vec(),
+            Endianness::Little => (len as u64).to_le_bytes()[..width].to_vec()
+        };
+
+        Ok(bytes)
+    }
+
+    fn decode_prefix(&self, prefix: &[u8]) -> Result<usize> {
+        let mut bytes = [0u8; 8];
+
+        let len = match self.endianness {
+            Endianness::Big => {
+                bytes[8 - prefix.len()..].copy_from_slice(prefix);
+                u64::from_be_bytes(bytes)
+            },
+            Endianness::Little => {
+                bytes[..prefix.len()].copy_from_slice(prefix);
+                u64::from_le_bytes(bytes)
+            }
+        };
+
+        if len > self.max_frame_size as u64 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("A frame of {} bytes is too large", len)));
+        }
+
+        Ok(len as usize)
+    }
+}
+
+impl FramedStream {
+    // Reads the next frame
+    // Returns None if the client closed the connection between frames
+    // The read is canceled when cancelable is canceled; afterwards the stream may be partway
+    // through a frame, so it shouldn't be read from again
+    pub async fn read_frame(&mut self, cancelable: &Cancelable) -> Result<Option<Vec<u8>>> {
+        cancelable.allow_cancel(
+            self.read_frame_int(),
+            Err(server_terminated()))
+            .await
+    }
+
+    // Writes a frame, and flushes it to the client
+    pub async fn write_frame(&mut self, frame: &[u8], cancelable: &Cancelable) -> Result<()> {
+        let prefix = self.codec.encode_prefix(frame.len())?;
+        let timeouts = self.timeouts;
+
+        cancelable.allow_cancel(
+            timeouts.write(async {
+                self.writer.write_all(&prefix).await?;
+                self.writer.write_all(frame).await?;
+                self.writer.flush().await
+            }),
+            Err(server_terminated()))
+            .await
+    }
+
+    async fn read_frame_int(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut prefix = vec![0u8; self.codec.prefix_width.bytes()];
+        let timeouts = self.timeouts;
+
+        // The first byte is read on its own to tell a closed connection apart from a truncated
+        // frame; waiting for it is the idle time between frames
+        if timeouts.idle(self.reader.read(&mut prefix[..1])).await? == 0 {
+            return Ok(None);
+        }
+
+        timeouts.read(async {
+            self.reader.read_exact(&mut prefix[1..]).await?;
+
+            let len = self.codec.decode_prefix(&prefix)?;
+
+            // The frame grows as its bytes arrive, so that a client can't make the server
+            // allocate max_frame_size just by sending a prefix
+            let mut frame = Vec::new();
+            (&mut self.reader).take(len as u64).read_to_end(&mut frame).await?;
+
+            if frame.len() != len {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "The client closed the connection partway through a frame"));
+            }
+
+            Ok(Some(frame))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::net::{ TcpListener, TcpStream };
+
+    use sync_tokens::cancelation_token::CancelationToken;
+
+    use super::*;
+
+    // A FramedStream for the server side of a loopback connection, and the client side
+    async fn connected(codec: FrameCodec) -> (FramedStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        (codec.framed(ConnectionStream::plain(server), ConnectionTimeouts::default()), client)
+    }
+
+    #[test]
+    fn prefixes_round_trip() {
+        let widths = [PrefixWidth::U8, PrefixWidth::U16, PrefixWidth::U32, PrefixWidth::U64];
+
+        for &prefix_width in widths.iter() {
+            for &endianness in [Endianness::Big, Endianness::Little].iter() {
+                let codec = FrameCodec::new().prefix_width(prefix_width).endianness(endianness);
+
+                for &len in [0, 1, 200, 255].iter() {
+                    let prefix = codec.encode_prefix(len).unwrap();
+                    assert_eq!(prefix.len(), prefix_width.bytes());
+                    assert_eq!(codec.decode_prefix(&prefix).unwrap(), len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prefixes_use_the_byte_order() {
+        let big = FrameCodec::new();
+        let little = FrameCodec::new().endianness(Endianness::Little);
+
+        assert_eq!(big.encode_prefix(0x0102).unwrap(), vec![0, 0, 1, 2]);
+        assert_eq!(little.encode_prefix(0x0102).unwrap(), vec![2, 1, 0, 0]);
+        assert_eq!(big.decode_prefix(&[0, 0, 1, 2]).unwrap(), 0x0102);
+        assert_eq!(little.decode_prefix(&[2, 1, 0, 0]).unwrap(), 0x0102);
+    }
+
+    #[test]
+    fn encode_prefix_rejects_lengths_that_dont_fit() {
+        let codec = FrameCodec::new().prefix_width(PrefixWidth::U8);
+
+        assert!(codec.encode_prefix(255).is_ok());
+        assert_eq!(codec.encode_prefix(256).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let codec = FrameCodec::new().prefix_width(PrefixWidth::U16).max_frame_size(usize::MAX);
+        assert_eq!(codec.encode_prefix(65536).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn frames_larger_than_the_maximum_are_rejected() {
+        let codec = FrameCodec::new().max_frame_size(100);
+
+        assert!(codec.encode_prefix(100).is_ok());
+        assert_eq!(codec.encode_prefix(101).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(codec.decode_prefix(&[0, 0, 0, 100]).unwrap(), 100);
+        assert_eq!(codec.decode_prefix(&[0, 0, 0, 101]).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // A prefix too large for usize is rejected without overflowing
+        let codec = FrameCodec::new().prefix_width(PrefixWidth::U64);
+        assert_eq!(codec.decode_prefix(&[0xff; 8]).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[async_std::test]
+    async fn frames_are_read_and_written() {
+        let (mut framed, mut client) = connected(FrameCodec::new()).await;
+        let (_cancelation_token, cancelable) = CancelationToken::new();
+
+        client.write_all(&[0, 0, 0, 5]).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        assert_eq!(framed.read_frame(&cancelable).await.unwrap(), Some(b"hello".to_vec()));
+
+        framed.write_frame(b"bye", &cancelable).await.unwrap();
+        let mut reply = [0u8; 7];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"\0\0\0\x03bye");
+
+        // Closing between frames isn't an error
+        drop(client);
+        assert_eq!(framed.read_frame(&cancelable).await.unwrap(), None);
+    }
+
+    #[async_std::test]
+    async fn a_truncated_frame_is_an_error() {
+        let (mut framed, mut client) = connected(FrameCodec::new()).await;
+        let (_cancelation_token, cancelable) = CancelationToken::new();
+
+        // The prefix promises far more than is sent
+        client.write_all(&[0, 0x10, 0, 0]).await.unwrap();
+        client.write_all(b"short").await.unwrap();
+        drop(client);
+
+        assert_eq!(framed.read_frame(&cancelable).await.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
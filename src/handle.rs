@@ -0,0 +1,151 @@
+use std::io::Error;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+
+use async_std::io::Result;
+use async_std::task::JoinHandle;
+
+use sync_tokens::cancelation_token::CancelationToken;
+use sync_tokens::completion_token::CompletionToken;
+
+use crate::config::{ ReloadableConfig, ServerConfig };
+use crate::metrics::ServerMetrics;
+use crate::reload_token::ReloadToken;
+use crate::server::ServerOutcome;
+
+// Completes with the local addresses once the server is listening
+type StartupToken = CompletionToken<Result<Vec<SocketAddr>>>;
+
+// Returned from run_server, used to wait for the server to start, stop it, and wait for it
+// to stop
+pub struct ServerHandle {
+    // Only None after join, which needs to take it out of the handle because of Drop
+    server_future: Option<JoinHandle<ServerOutcome>>,
+    // Pinned so that ready can await it in place; it's only taken out once it completes, so a
+    // call to ready that is dropped partway doesn't lose the result
+    completion_token: Option<Pin<Box<StartupToken>>>,
+    local_addrs: Option<Result<Vec<SocketAddr>>>,
+    cancelation_token: CancelationToken,
+    reload_token: ReloadToken<ReloadableConfig>,
+    // The drain timeout passed to shutdown_graceful
+    graceful_token: ReloadToken<Option<Duration>>,
+    // The settings that the server was started with
+    config: ServerConfig,
+    metrics: ServerMetrics,
+    cancel_on_drop: bool
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        server_future: JoinHandle<ServerOutcome>,
+        completion_token: StartupToken,
+        cancelation_token: CancelationToken,
+        reload_token: ReloadToken<ReloadableConfig>,
+        graceful_token: ReloadToken<Option<Duration>>,
+        config: ServerConfig,
+        metrics: ServerMetrics) -> ServerHandle {
+
+        ServerHandle {
+            server_future: Some(server_future),
+            completion_token: Some(Box::pin(completion_token)),
+            local_addrs: None,
+            cancelation_token,
+            reload_token,
+            graceful_token,
+            config,
+            metrics,
+            cancel_on_drop: false
+        }
+    }
+
+    // Stops the server when the handle is dropped
+    // This prevents leaking a listener when the handle goes out of scope, for example when a
+    // test fails before it stops its server. Dropping doesn't wait for the server to stop,
+    // because blocking could deadlock the executor; use shutdown_and_join to wait.
+    pub fn cancel_on_drop(mut self) -> Self {
+        self.cancel_on_drop = true;
+        self
+    }
+
+    // Waits until the server is listening, and returns the local address of every listener
+    // Returns an error if the server failed to start
+    pub async fn ready(&mut self) -> Result<Vec<SocketAddr>> {
+        if let Some(completion_token) = self.completion_token.as_mut() {
+            let local_addrs = completion_token.as_mut().await;
+            self.local_addrs = Some(local_addrs);
+            self.completion_token = None;
+        }
+
+        match &self.local_addrs {
+            Some(Ok(local_addrs)) => Ok(local_addrs.clone()),
+            // Error can't be cloned, so a copy is made with the same kind and message
+            Some(Err(err)) => Err(Error::new(err.kind(), err.to_string())),
+            // The CompletionToken is only cleared once local_addrs is set
+            None => unreachable!()
+        }
+    }
+
+    // The local address of the first listener, once ready has returned successfully
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().first().cloned()
+    }
+
+    // The local addresses of all listeners, once ready has returned successfully
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        match &self.local_addrs {
+            Some(Ok(local_addrs)) => local_addrs,
+            _ => &[]
+        }
+    }
+
+    pub fn metrics(&self) -> &ServerMetrics {
+        &self.metrics
+    }
+
+    // Applies new settings to the running server
+    // Listener settings can't be changed without restarting the server, so they are ignored;
+    // returns false if they differ from the settings that the server was started with
+    pub fn reload(&self, config: &ServerConfig) -> bool {
+        self.reload_token.reload(config.reloadable());
+        self.config.same_listeners(config)
+    }
+
+    // Stops the server
+    // In-flight connections have ServerConfig::drain_timeout to finish, or are canceled
+    // immediately if it wasn't set
+    pub fn shutdown(&self) {
+        self.cancelation_token.cancel();
+    }
+
+    // Stops the server, giving in-flight connections up to timeout to finish
+    pub fn shutdown_graceful(&self, timeout: Duration) {
+        // Set before canceling, so that the server sees it when it stops accepting
+        self.graceful_token.reload(Some(timeout));
+        self.cancelation_token.cancel();
+    }
+
+    // Waits for the server to stop
+    pub async fn join(mut self) -> ServerOutcome {
+        self.server_future.take().unwrap().await
+    }
+
+    // Stops the server, and waits for it to stop
+    pub async fn shutdown_and_join(self) -> ServerOutcome {
+        self.shutdown();
+        self.join().await
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        // A call to join that is dropped before the server stops has already taken
+        // server_future, so the server is canceled either way; canceling a server that already
+        // stopped does nothing
+        // The server keeps running on its own task until it has drained or canceled its
+        // connections
+        if self.cancel_on_drop {
+            self.shutdown();
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use async_std::io::Result;
+use async_std::io::prelude::*;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use crate::connection::server_terminated;
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+
+// Reads from the client until it closes the connection, ignoring everything that it sends
+pub async fn discard(mut stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> Result<()> {
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let read = cancelable.allow_cancel(
+            timeouts.idle(stream.read(&mut buf)),
+            Err(server_terminated()))
+            .await?;
+
+        if read == 0 {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use async_std::io::Result;
+use async_std::io::prelude::*;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use crate::connection::server_terminated;
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+
+// Writes everything that the client sends back to it, until the client closes the connection
+// This is the simplest example of a handler that stops when the server is canceled: each read
+// and write is raced against the connection's Cancelable, so a client that is idle doesn't keep
+// the connection open after the server is canceled
+pub async fn echo(stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> Result<()> {
+    // ConnectionStream can be read from and written to through shared references
+    let mut reader = &stream;
+    let mut writer = &stream;
+
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let read = cancelable.allow_cancel(
+            timeouts.idle(reader.read(&mut buf)),
+            Err(server_terminated()))
+            .await?;
+
+        if read == 0 {
+            return Ok(());
+        }
+
+        cancelable.allow_cancel(
+            // Flushing makes sure that nothing is left buffered in a TLS session
+            timeouts.write(async {
+                writer.write_all(&buf[..read]).await?;
+                writer.flush().await
+            }),
+            Err(server_terminated()))
+            .await?;
+    }
+}
@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+use std::io::{ Error, ErrorKind };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU8, Ordering };
+
+use async_std::channel::Receiver;
+use async_std::io::{ BufReader, Result };
+use async_std::io::prelude::*;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use crate::connection::{ server_terminated, ConnectionFuture, ConnectionHandler, ConnectionTracker };
+use crate::handlers::wait_for_data;
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+
+// The request line and headers together can't be longer than this
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+// Request bodies larger than this are rejected, unless HttpProtocol::max_body_size is called
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// A request from a client
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    // The path and query string, as sent by the client
+    pub target: String,
+    // 0 for HTTP/1.0, 1 for HTTP/1.1
+    pub minor_version: u8,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+// A response to send to the client
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Body
+}
+
+enum Body {
+    Full(Vec<u8>),
+    // Sent with chunked transfer encoding, one chunk for each Vec received, until the channel
+    // is closed
+    Stream(Receiver<Vec<u8>>)
+}
+
+// A route is called with the request, and returns the response
+type Route = dyn Fn(&Request) -> Response + Send + Sync;
+
+// Values of HttpProtocolInner::readiness
+// The server is NOT_STARTED until the CompletionToken is completed, and SHUTTING_DOWN from when
+// it's canceled
+const NOT_STARTED: u8 = 0;
+const READY: u8 = 1;
+const SHUTTING_DOWN: u8 = 2;
+
+// A minimal HTTP/1.1 server, routes requests by method and path
+// Supports keep-alive, Content-Length request bodies, and chunked responses. When the server is
+// canceled, idle keep-alive connections are closed right away; requests that are in flight
+// have until the drain timeout to finish.
+//
+// let protocol = HttpProtocol::new()
+//     .route("GET", "/", |_| Response::text(200, "Hello"));
+// run_server(config, protocol);
+#[derive(Clone)]
+pub struct HttpProtocol {
+    inner: Arc<HttpProtocolInner>
+}
+
+// Cloned by the builder methods when a clone of the HttpProtocol still shares it
+// The clone shares idle_connections and readiness with the original
+#[derive(Clone)]
+struct HttpProtocolInner {
+    // Keyed by path, and then by method
+    routes: HashMap<String, HashMap<String, Arc<Route>>>,
+    max_body_size: usize,
+    // Canceled when the server starts shutting down, used to close idle connections
+    idle_connections: ConnectionTracker,
+    // Reported by /readyz, shared with its route
+    readiness: Arc<AtomicU8>
+}
+
+// What to do after a response is sent
+#[derive(Clone, Copy, PartialEq)]
+enum Persist {
+    KeepAlive,
+    Close
+}
+
+impl Request {
+    // The value of the first header with this name, names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The target without the query string
+    pub fn path(&self) -> &str {
+        match self.target.find('?') {
+            Some(question_mark) => &self.target[..question_mark],
+            None => &self.target
+        }
+    }
+
+    fn wants_keep_alive(&self) -> bool {
+        let connection = self.header("Connection").unwrap_or("");
+
+        if self.minor_version == 0 {
+            connection.eq_ignore_ascii_case("keep-alive")
+        } else {
+            !connection.eq_ignore_ascii_case("close")
+        }
+    }
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Full(Vec::new())
+        }
+    }
+
+    // A plain text response
+    pub fn text(status: u16, text: &str) -> Response {
+        Response::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(text.as_bytes().to_vec())
+    }
+
+    // A response whose body is sent in chunks as they are received from chunks
+    // The response ends when the sending side of the channel is dropped
+    pub fn stream(status: u16, chunks: Receiver<Vec<u8>>) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Stream(chunks)
+        }
+    }
+
+    // Content-Length, Transfer-Encoding and Connection are set automatically
+    // Line breaks are removed from the name and value, so that a value that came from a client
+    // can't add headers of its own or end the head early
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((strip_line_breaks(name), strip_line_breaks(value)));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Response {
+        self.body = Body::Full(body);
+        self
+    }
+}
+
+impl Default for HttpProtocol {
+    fn default() -> Self {
+        HttpProtocol {
+            inner: Arc::new(HttpProtocolInner {
+                routes: HashMap::new(),
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+                idle_connections: ConnectionTracker::default(),
+                readiness: Arc::new(AtomicU8::new(NOT_STARTED))
+            })
+        }
+    }
+}
+
+impl HttpProtocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a route, paths must match exactly and don't include the query string
+    pub fn route<F>(mut self, method: &str, path: &str, route: F) -> Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.inner_mut()
+            .routes
+            .entry(path.to_string())
+            .or_default()
+            .insert(method.to_string(), Arc::new(route));
+
+        self
+    }
+
+    // The largest request body that a client can send
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.inner_mut().max_body_size = max_body_size;
+        self
+    }
+
+    // Adds GET /healthz and GET /readyz, for load balancers and orchestrators
+    // /healthz is the liveness check, it succeeds whenever the server can answer at all
+    // /readyz succeeds once the server is listening, and fails with 503 as soon as the server
+    // is canceled, so that load balancers stop sending new requests while in-flight ones drain
+    pub fn health_checks(self) -> Self {
+        let readiness = self.inner.readiness.clone();
+
+        self
+            .route("GET", "/healthz", |_| Response::text(200, "ok\n"))
+            .route("GET", "/readyz", move |_| match readiness.load(Ordering::SeqCst) {
+                READY => Response::text(200, "ready\n"),
+                SHUTTING_DOWN => Response::text(503, "shutting down\n").header("Retry-After", "1"),
+                _ => Response::text(503, "starting\n")
+            })
+    }
+
+    // Changing a clone doesn't change the protocol it was cloned from
+    fn inner_mut(&mut self) -> &mut HttpProtocolInner {
+        Arc::make_mut(&mut self.inner)
+    }
+}
+
+impl ConnectionHandler for HttpProtocol {
+    fn handle(&self, stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> ConnectionFuture {
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.run(stream, cancelable, timeouts).await })
+    }
+
+    fn started(&self) {
+        self.inner.readiness.store(READY, Ordering::SeqCst);
+    }
+
+    fn shutdown_started(&self) {
+        self.inner.readiness.store(SHUTTING_DOWN, Ordering::SeqCst);
+        self.inner.idle_connections.cancel_all();
+    }
+}
+
+impl HttpProtocolInner {
+    async fn run(&self, stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> Result<()> {
+        let mut reader = BufReader::new(&stream);
+        let mut writer = &stream;
+
+        // idle_cancelable is only used while waiting for the next request
+        let (_idle_guard, idle_cancelable) = self.idle_connections.register();
+
+        loop {
+            // Wait for the next request to start, unless the client already sent it. If the
+            // server starts shutting down while waiting, the connection is closed cleanly.
+            let started = if !reader.buffer().is_empty() {
+                Some(Ok(true))
+            } else {
+                idle_cancelable.allow_cancel(
+                    async {
+                        let waiting = timeouts.idle(wait_for_data(&mut reader));
+                        Some(cancelable.allow_cancel(waiting, Err(server_terminated())).await)
+                    },
+                    None)
+                    .await
+            };
+
+            match started {
+                Some(Ok(true)) => {},
+                // Either the client closed the connection, or the server is shutting down
+                Some(Ok(false)) | None => return Ok(()),
+                // Closing idle keep-alive connections is routine, so it isn't reported as an
+                // error
+                Some(Err(err)) if err.kind() == ErrorKind::TimedOut => return Ok(()),
+                Some(Err(err)) => return Err(err)
+            }
+
+            // Once a request has started, only the connection's Cancelable can stop it
+            let request = cancelable.allow_cancel(
+                timeouts.read(self.read_request(&mut reader)),
+                Err(server_terminated()))
+                .await?;
+
+            let (response, minor_version, persist) = match request {
+                Ok(request) => {
+                    // The response is still sent when the server is shutting down, but the
+                    // client is told not to send another request on this connection
+                    let persist = if request.wants_keep_alive() && self.readiness.load(Ordering::SeqCst) != SHUTTING_DOWN {
+                        Persist::KeepAlive
+                    } else {
+                        Persist::Close
+                    };
+
+                    (self.dispatch(&request), request.minor_version, persist)
+                },
+                // The request couldn't be parsed, so the rest of the stream can't be trusted
+                Err(response) => (response, 1, Persist::Close)
+            };
+
+            let persist = cancelable.allow_cancel(
+                write_response(&mut writer, response, minor_version, persist, &timeouts),
+                Err(server_terminated()))
+                .await?;
+
+            if persist == Persist::Close {
+                return Ok(());
+            }
+        }
+    }
+
+    // Reads the request line, headers and body
+    // Returns Ok(Err(response)) when the request is invalid, with the response to send
+    async fn read_request(&self, reader: &mut BufReader<&ConnectionStream>) -> Result<std::result::Result<Request, Response>> {
+        let mut head = Vec::new();
+
+        // Read lines until the empty line that ends the headers
+        loop {
+            let remaining = (MAX_HEAD_SIZE - head.len()) as u64;
+            let read = reader.take(remaining).read_until(b'\n', &mut head).await?;
+
+            if read == 0 || !head.ends_with(b"\n") {
+                if head.len() >= MAX_HEAD_SIZE {
+                    return Ok(Err(Response::text(431, "Request headers are too large")));
+                }
+
+                return Err(Error::new(ErrorKind::UnexpectedEof, "The client closed the connection partway through a request"));
+            }
+
+            if head.ends_with(b"\n\n") || head.ends_with(b"\n\r\n") {
+                break;
+            }
+        }
+
+        let mut request = match parse_head(&head) {
+            Some(request) => request,
+            None => return Ok(Err(Response::text(400, "Invalid request")))
+        };
+
+        if request.header("Transfer-Encoding").is_some() {
+            return Ok(Err(Response::text(501, "Chunked request bodies are not supported")));
+        }
+
+        let content_length = match content_length(&request) {
+            Some(content_length) => content_length,
+            None => return Ok(Err(Response::text(400, "Invalid Content-Length")))
+        };
+
+        if content_length > self.max_body_size {
+            return Ok(Err(Response::text(413, "Request body is too large")));
+        }
+
+        request.body = vec![0u8; content_length];
+        reader.read_exact(&mut request.body).await?;
+
+        Ok(Ok(request))
+    }
+
+    fn dispatch(&self, request: &Request) -> Response {
+        let methods = match self.routes.get(request.path()) {
+            Some(methods) => methods,
+            None => return Response::text(404, "Not found")
+        };
+
+        match methods.get(&request.method) {
+            Some(route) => route(request),
+            None => {
+                let mut allowed: Vec<&str> = methods.keys().map(|method| method.as_str()).collect();
+                allowed.sort_unstable();
+
+                Response::text(405, "Method not allowed")
+                    .header("Allow", &allowed.join(", "))
+            }
+        }
+    }
+}
+
+// Parses the request line and headers, returns None if they aren't valid
+fn parse_head(head: &[u8]) -> Option<Request> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut lines = head.split('\n').map(|line| line.trim_end_matches('\r'));
+
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?;
+    let target = request_line.next()?;
+
+    let minor_version = match request_line.next()? {
+        "HTTP/1.0" => 0,
+        "HTTP/1.1" => 1,
+        _ => return None
+    };
+
+    if method.is_empty() || target.is_empty() || request_line.next().is_some() {
+        return None;
+    }
+
+    let mut headers = Vec::new();
+
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let colon = line.find(':')?;
+        headers.push((line[..colon].trim().to_string(), line[colon + 1..].trim().to_string()));
+    }
+
+    Some(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        minor_version,
+        headers,
+        body: Vec::new()
+    })
+}
+
+// The length of the request body, 0 if there is no Content-Length
+// Returns None if a Content-Length isn't a number, or if there are several that disagree,
+// since the client and any proxy in between could then disagree about where the body ends
+fn content_length(request: &Request) -> Option<usize> {
+    let mut content_length = None;
+
+    let values = request.headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .flat_map(|(_, value)| value.split(','));
+
+    for value in values {
+        let length = value.trim().parse::<usize>().ok()?;
+
+        if content_length.is_some() && content_length != Some(length) {
+            return None;
+        }
+
+        content_length = Some(length);
+    }
+
+    Some(content_length.unwrap_or(0))
+}
+
+// Writes the response, and returns whether the connection can be used for another request
+// Each write is raced against the write timeout on its own, so that a streamed response can
+// take as long as it needs to produce its chunks
+async fn write_response(writer: &mut &ConnectionStream, response: Response, minor_version: u8, persist: Persist, timeouts: &ConnectionTimeouts) -> Result<Persist> {
+    // HTTP/1.0 doesn't have chunked encoding, so streamed bodies end by closing the connection
+    let persist = match (&response.body, minor_version) {
+        (Body::Stream(_), 0) => Persist::Close,
+        _ => persist
+    };
+
+    let mut head = format!("HTTP/1.{} {} {}\r\n", minor_version, response.status, reason_phrase(response.status));
+
+    for (name, value) in response.headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    match &response.body {
+        Body::Full(body) => head.push_str(&format!("Content-Length: {}\r\n", body.len())),
+        Body::Stream(_) if minor_version > 0 => head.push_str("Transfer-Encoding: chunked\r\n"),
+        Body::Stream(_) => {}
+    }
+
+    match (persist, minor_version) {
+        (Persist::Close, _) => head.push_str("Connection: close\r\n"),
+        (Persist::KeepAlive, 0) => head.push_str("Connection: keep-alive\r\n"),
+        (Persist::KeepAlive, _) => {}
+    }
+
+    head.push_str("\r\n");
+
+    match response.body {
+        Body::Full(body) => {
+            let mut bytes = head.into_bytes();
+            bytes.extend_from_slice(&body);
+            timeouts.write(writer.write_all(&bytes)).await?;
+        },
+        Body::Stream(chunks) => {
+            timeouts.write(writer.write_all(head.as_bytes())).await?;
+
+            while let Ok(chunk) = chunks.recv().await {
+                // An empty chunk would end the response early
+                if chunk.is_empty() {
+                    continue;
+                }
+
+                let bytes = if minor_version > 0 {
+                    let mut bytes = format!("{:x}\r\n", chunk.len()).into_bytes();
+                    bytes.extend_from_slice(&chunk);
+                    bytes.extend_from_slice(b"\r\n");
+                    bytes
+                } else {
+                    chunk
+                };
+
+                timeouts.write(writer.write_all(&bytes)).await?;
+            }
+
+            if minor_version > 0 {
+                timeouts.write(writer.write_all(b"0\r\n\r\n")).await?;
+            }
+        }
+    }
+
+    timeouts.write(writer.flush()).await?;
+
+    Ok(persist)
+}
+
+fn strip_line_breaks(text: &str) -> String {
+    text.chars().filter(|c| *c != '\r' && *c != '\n').collect()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::net::{ TcpListener, TcpStream };
+
+    use super::*;
+
+    // Sends raw to a loopback connection, and reads it back as a request
+    async fn read_request(protocol: &HttpProtocol, raw: &[u8]) -> Result<std::result::Result<Request, Response>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (server, _) = listener.accept().await?;
+
+        client.write_all(raw).await?;
+        drop(client);
+
+        let stream = ConnectionStream::plain(server);
+        let mut reader = BufReader::new(&stream);
+        protocol.inner.read_request(&mut reader).await
+    }
+
+    fn status(request: std::result::Result<Request, Response>) -> u16 {
+        match request {
+            Ok(_) => 200,
+            Err(response) => response.status
+        }
+    }
+
+    #[test]
+    fn parse_head_reads_the_request_line_and_headers() {
+        let request = parse_head(b"GET /path?query=1 HTTP/1.1\r\nHost: example.com\r\nX-Empty:\r\n\r\n").unwrap();
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.target, "/path?query=1");
+        assert_eq!(request.path(), "/path");
+        assert_eq!(request.minor_version, 1);
+        assert_eq!(request.header("host"), Some("example.com"));
+        assert_eq!(request.header("X-Empty"), Some(""));
+        assert_eq!(request.header("Missing"), None);
+    }
+
+    #[test]
+    fn parse_head_accepts_bare_line_feeds() {
+        let request = parse_head(b"POST / HTTP/1.0\nContent-Length: 3\n\n").unwrap();
+
+        assert_eq!(request.minor_version, 0);
+        assert_eq!(request.header("Content-Length"), Some("3"));
+    }
+
+    #[test]
+    fn parse_head_rejects_invalid_heads() {
+        assert!(parse_head(b"GET /\r\n\r\n").is_none());
+        assert!(parse_head(b"GET / HTTP/2.0\r\n\r\n").is_none());
+        assert!(parse_head(b"GET / HTTP/1.1 extra\r\n\r\n").is_none());
+        assert!(parse_head(b" / HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_head(b"GET / HTTP/1.1\r\nNo colon\r\n\r\n").is_none());
+        assert!(parse_head(b"GET / HTTP/1.1\r\nHost: \xff\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn keep_alive_depends_on_the_version() {
+        let request = |head: &[u8]| parse_head(head).unwrap();
+
+        assert!(request(b"GET / HTTP/1.1\r\n\r\n").wants_keep_alive());
+        assert!(!request(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").wants_keep_alive());
+        assert!(!request(b"GET / HTTP/1.0\r\n\r\n").wants_keep_alive());
+        assert!(request(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").wants_keep_alive());
+    }
+
+    #[test]
+    fn response_headers_cant_contain_line_breaks() {
+        let response = Response::new(200).header("X-Name\r\n", "value\r\nSet-Cookie: injected");
+
+        assert_eq!(response.headers, vec![("X-Name".to_string(), "valueSet-Cookie: injected".to_string())]);
+    }
+
+    #[async_std::test]
+    async fn read_request_reads_the_body() {
+        let request = read_request(&HttpProtocol::new(), b"POST /submit HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").await.unwrap().ok().unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[async_std::test]
+    async fn read_request_accepts_duplicate_content_lengths_that_agree() {
+        let request = read_request(&HttpProtocol::new(), b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2, 2\r\n\r\nhi").await.unwrap().ok().unwrap();
+
+        assert_eq!(request.body, b"hi");
+    }
+
+    #[async_std::test]
+    async fn read_request_rejects_invalid_requests() {
+        let protocol = HttpProtocol::new().max_body_size(4);
+
+        let cases: [(&[u8], u16); 6] = [
+            (b"NOT HTTP\r\n\r\n", 400),
+            (b"POST / HTTP/1.1\r\nContent-Length: nope\r\n\r\n", 400),
+            (b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nhi", 400),
+            (b"POST / HTTP/1.1\r\nContent-Length: 2, 3\r\n\r\nhi", 400),
+            (b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello", 413),
+            (b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n", 501)
+        ];
+
+        for (raw, expected) in cases.iter() {
+            assert_eq!(status(read_request(&protocol, raw).await.unwrap()), *expected);
+        }
+    }
+
+    #[async_std::test]
+    async fn read_request_limits_the_head() {
+        let mut raw = b"GET / HTTP/1.1\r\nX-Long: ".to_vec();
+        raw.resize(MAX_HEAD_SIZE + 10, b'a');
+
+        assert_eq!(status(read_request(&HttpProtocol::new(), &raw).await.unwrap()), 431);
+    }
+
+    #[async_std::test]
+    async fn read_request_fails_when_the_client_closes_early() {
+        let err = read_request(&HttpProtocol::new(), b"GET / HTTP/1.1\r\nHost:").await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        let err = read_request(&HttpProtocol::new(), b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhi").await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn dispatch_routes_by_path_and_method() {
+        let protocol = HttpProtocol::new()
+            .route("GET", "/", |_| Response::text(200, "get"))
+            .route("POST", "/", |_| Response::text(201, "post"));
+
+        let request = |head: &[u8]| parse_head(head).unwrap();
+
+        assert_eq!(protocol.inner.dispatch(&request(b"GET /?q HTTP/1.1\r\n\r\n")).status, 200);
+        assert_eq!(protocol.inner.dispatch(&request(b"POST / HTTP/1.1\r\n\r\n")).status, 201);
+        assert_eq!(protocol.inner.dispatch(&request(b"GET /missing HTTP/1.1\r\n\r\n")).status, 404);
+
+        let not_allowed = protocol.inner.dispatch(&request(b"PUT / HTTP/1.1\r\n\r\n"));
+        assert_eq!(not_allowed.status, 405);
+        assert!(not_allowed.headers.contains(&("Allow".to_string(), "GET, POST".to_string())));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_std::io::{ BufReader, Result };
+use async_std::io::prelude::*;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use crate::connection::{ server_terminated, ConnectionFuture, ConnectionHandler };
+use crate::handlers::wait_for_data;
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+
+// Lines longer than this close the connection, unless LineProtocol::max_line_length is called
+const DEFAULT_MAX_LINE_LENGTH: usize = 1024;
+
+// What a command sends back to the client
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    // Sends the line and waits for the next command
+    Line(String),
+    // Sends the line and closes the connection
+    Close(String)
+}
+
+// A command is called with everything on the line after the command's name
+type Command = dyn Fn(&str) -> Reply + Send + Sync;
+
+// A handler for protocols where each line that the client sends is a command, like SMTP or
+// Redis's inline commands
+// The first word on the line picks the command, and the command's reply is sent back as a
+// single line. Lines are ended with \n, and an optional \r before it is ignored.
+//
+// let protocol = LineProtocol::new()
+//     .command("PING", |_| Reply::Line("PONG".to_string()))
+//     .command("QUIT", |_| Reply::Close("BYE".to_string()));
+// run_server(config, protocol);
+#[derive(Clone)]
+pub struct LineProtocol {
+    inner: Arc<LineProtocolInner>
+}
+
+// Cloned by the builder methods when a clone of the LineProtocol still shares it
+#[derive(Clone)]
+struct LineProtocolInner {
+    commands: HashMap<String, Arc<Command>>,
+    max_line_length: usize
+}
+
+impl Default for LineProtocol {
+    fn default() -> Self {
+        LineProtocol {
+            inner: Arc::new(LineProtocolInner {
+                commands: HashMap::new(),
+                max_line_length: DEFAULT_MAX_LINE_LENGTH
+            })
+        }
+    }
+}
+
+impl LineProtocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a command, names are case-sensitive
+    pub fn command<F>(mut self, name: &str, command: F) -> Self
+    where
+        F: Fn(&str) -> Reply + Send + Sync + 'static
+    {
+        self.inner_mut().commands.insert(name.to_string(), Arc::new(command));
+        self
+    }
+
+    // The longest line that a client can send, not counting the line ending
+    // A longer line gets an error reply, and the connection is closed
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.inner_mut().max_line_length = max_line_length;
+        self
+    }
+
+    // Changing a clone doesn't change the protocol it was cloned from
+    fn inner_mut(&mut self) -> &mut LineProtocolInner {
+        Arc::make_mut(&mut self.inner)
+    }
+}
+
+impl ConnectionHandler for LineProtocol {
+    fn handle(&self, stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> ConnectionFuture {
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.run(stream, cancelable, timeouts).await })
+    }
+}
+
+impl LineProtocolInner {
+    async fn run(&self, stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> Result<()> {
+        let mut reader = BufReader::new(&stream);
+        let mut writer = &stream;
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+
+            // Wait for the next command
+            // (The wait is canceled when the server is canceled, even if the client is idle)
+            let started = cancelable.allow_cancel(
+                timeouts.idle(wait_for_data(&mut reader)),
+                Err(server_terminated()))
+                .await?;
+
+            // The client closed the connection
+            if !started {
+                return Ok(());
+            }
+
+            // Reading is limited to one byte past the longest line, so that a client can't use
+            // up memory by never sending a line ending
+            let limit = self.max_line_length as u64 + 2;
+            cancelable.allow_cancel(
+                timeouts.read((&mut reader).take(limit).read_until(b'\n', &mut line)),
+                Err(server_terminated()))
+                .await?;
+
+            let reply = match parse_line(&line, self.max_line_length) {
+                Ok("") => continue,
+                Ok(line) => self.dispatch(line),
+                Err(reply) => reply
+            };
+
+            let (text, close) = match reply {
+                Reply::Line(text) => (text, false),
+                Reply::Close(text) => (text, true)
+            };
+
+            cancelable.allow_cancel(
+                timeouts.write(write_line(&mut writer, &text)),
+                Err(server_terminated()))
+                .await?;
+
+            if close {
+                return Ok(());
+            }
+        }
+    }
+
+    fn dispatch(&self, line: &str) -> Reply {
+        let (name, arguments) = match line.find(' ') {
+            Some(space) => (&line[..space], line[space + 1..].trim_start()),
+            None => (line, "")
+        };
+
+        match self.commands.get(name) {
+            Some(command) => command(arguments),
+            None => Reply::Line(format!("ERR unknown command '{}'", name))
+        }
+    }
+}
+
+// Removes the line ending, and checks that the line is valid
+// Invalid lines are turned into the reply that is sent to the client
+fn parse_line(line: &[u8], max_line_length: usize) -> std::result::Result<&str, Reply> {
+    // Without a line ending, either the line is too long or the client closed the connection
+    // partway through the line; in that case the partial line is still handled
+    let line = match line.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None if line.len() > max_line_length => return Err(Reply::Close("ERR line too long".to_string())),
+        None => line
+    };
+
+    if line.len() > max_line_length {
+        return Err(Reply::Close("ERR line too long".to_string()));
+    }
+
+    match std::str::from_utf8(line) {
+        Ok(line) => Ok(line.trim()),
+        Err(_) => Err(Reply::Line("ERR line is not valid UTF-8".to_string()))
+    }
+}
+
+async fn write_line(writer: &mut &ConnectionStream, text: &str) -> Result<()> {
+    writer.write_all(text.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+
+    // Nothing is left buffered in a TLS session while waiting for the next command
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_removes_line_endings() {
+        assert_eq!(parse_line(b"PING\n", 10), Ok("PING"));
+        assert_eq!(parse_line(b"PING\r\n", 10), Ok("PING"));
+        assert_eq!(parse_line(b"  ECHO hi  \r\n", 20), Ok("ECHO hi"));
+        assert_eq!(parse_line(b"\r\n", 10), Ok(""));
+    }
+
+    #[test]
+    fn parse_line_handles_a_partial_last_line() {
+        assert_eq!(parse_line(b"QUIT", 10), Ok("QUIT"));
+    }
+
+    #[test]
+    fn parse_line_rejects_long_lines() {
+        let too_long = Err(Reply::Close("ERR line too long".to_string()));
+
+        // The line ending doesn't count towards the limit
+        assert_eq!(parse_line(b"0123456789\r\n", 10), Ok("0123456789"));
+        assert_eq!(parse_line(b"0123456789A\n", 10), too_long);
+        assert_eq!(parse_line(b"0123456789AB", 10), too_long);
+    }
+
+    #[test]
+    fn parse_line_rejects_invalid_utf8() {
+        assert_eq!(parse_line(b"\xff\xfe\n", 10), Err(Reply::Line("ERR line is not valid UTF-8".to_string())));
+    }
+
+    #[test]
+    fn changing_a_clone_leaves_the_original_alone() {
+        let original = LineProtocol::new().command("PING", |_| Reply::Line("PONG".to_string()));
+        let changed = original.clone().command("QUIT", |_| Reply::Close("BYE".to_string()));
+
+        assert_eq!(original.inner.dispatch("QUIT"), Reply::Line("ERR unknown command 'QUIT'".to_string()));
+        assert_eq!(changed.inner.dispatch("QUIT"), Reply::Close("BYE".to_string()));
+        assert_eq!(changed.inner.dispatch("PING"), Reply::Line("PONG".to_string()));
+    }
+}
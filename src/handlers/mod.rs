@@ -0,0 +1,23 @@
+// Connection handlers that can be passed to run_server
+pub mod discard;
+pub mod echo;
+pub mod http;
+pub mod line;
+
+use std::pin::Pin;
+
+use async_std::future;
+use async_std::io::{ BufRead, Result };
+
+// Waits until the client sends something, without consuming it
+// Returns false if the client closed the connection instead
+// Protocols use this to wait for the next message with the idle timeout, and then read the
+// rest of it with the read timeout
+pub(crate) async fn wait_for_data<R: BufRead + Unpin>(reader: &mut R) -> Result<bool> {
+    future::poll_fn(|cx| {
+        Pin::new(&mut *reader)
+            .poll_fill_buf(cx)
+            .map_ok(|buf| !buf.is_empty())
+    })
+    .await
+}
@@ -0,0 +1,16 @@
+// A server skeleton that uses sync-tokens to signal when it's listening, and to stop it
+pub mod cidr;
+pub mod config;
+pub mod config_file;
+pub mod connection;
+pub mod framing;
+pub mod handle;
+pub mod handlers;
+pub mod metrics;
+mod peer_limits;
+pub mod reload_token;
+pub mod server;
+pub mod signals;
+pub mod stream;
+pub mod timeouts;
+mod tls;
@@ -1,80 +1,391 @@
-use std::io::{ Error, ErrorKind };
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, SystemTime };
 
+use async_std::channel;
+use async_std::channel::Sender;
+use async_std::fs;
 use async_std::io::Result;
-use async_std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream, SocketAddr};
+use async_std::io::prelude::*;
 use async_std::task;
-use async_std::task::JoinHandle;
 
-use sync_tokens::cancelation_token::{ Cancelable, CancelationToken };
-use sync_tokens::completion_token::{ Completable, CompletionToken };
+use clap::{ Parser, ValueEnum };
+use log::{ error, info, warn, LevelFilter };
 
-// Starts running a server on a background task
-pub fn run_server() -> (JoinHandle<Result<()>>, CompletionToken<Result<SocketAddr>>, CancelationToken) {
-    // This CompletionToken allows the caller to wait until the server is actually listening
-    // The caller gets completion_token, which it can await on
-    // completable is used to signal to completion_token
-    let (completion_token, completable) = CompletionToken::new();
+use sync_tokens::cancelation_token::Cancelable;
 
-    // This CancelationToken allows the caller to stop the server
-    // The caller gets cancelation_token
-    // cancelable is used to allow canceling a call to await
-    let (cancelation_token, cancelable) = CancelationToken::new();
+use sync_tokens_example::cidr::Cidr;
+use sync_tokens_example::config::ServerConfig;
+use sync_tokens_example::config_file::ConfigFile;
+use sync_tokens_example::connection::server_terminated;
+use sync_tokens_example::framing::FrameCodec;
+use sync_tokens_example::handle::ServerHandle;
+use sync_tokens_example::handlers::discard::discard;
+use sync_tokens_example::handlers::echo::echo;
+use sync_tokens_example::handlers::http::{ HttpProtocol, Response };
+use sync_tokens_example::handlers::line::{ LineProtocol, Reply };
+use sync_tokens_example::server::{ run_server, ServerOutcome };
+use sync_tokens_example::signals::{ SignalAction, SignalListener };
+use sync_tokens_example::stream::ConnectionStream;
+use sync_tokens_example::timeouts::ConnectionTimeouts;
 
-    // The server is started on a background task, and the future returned
-    let server_future = task::spawn(run_server_int(completable, cancelable));
+// Used when neither the configuration file nor the command line set a timeout
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
-    (server_future, completion_token, cancelation_token)
-}
+// How often the configuration file is checked for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
-async fn run_server_int(completable: Completable<Result<SocketAddr>>, cancelable: Cancelable) -> Result<()> {
+// Command-line arguments
+// Server settings are optional so that they only override the configuration file when given
+#[derive(Parser)]
+#[command(about = "Example server demonstrating sync-tokens")]
+struct Args {
+    /// TOML file with server settings, reloaded when it changes or on SIGHUP
+    #[arg(short, long)]
+    config: Option<PathBuf>,
 
-    let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
-    let listener = TcpListener::bind(socket_addr).await?;
+    /// Address to listen on, can be repeated to listen on several addresses (default 0.0.0.0)
+    #[arg(short, long = "address")]
+    addresses: Vec<IpAddr>,
 
-    // Inform that the server is listening
-    let local_addr = listener.local_addr();
-    completable.complete(local_addr);
+    /// Port to listen on, 0 picks an ephemeral port (default 0)
+    #[arg(short, long)]
+    port: Option<u16>,
 
-    // Create a future that waits for an incoming socket
-    let mut incoming_future = task::spawn(accept(listener));
-    
-    loop {
-        // Wait for either the incoming socket (via incoming_future) or for the CancelationToken
-        // to be canceled.
-        // When the CancelationToken is canceled, the error is returned
-        let (listener, _) = cancelable.allow_cancel(
-            incoming_future, 
-            Err(Error::new(ErrorKind::Interrupted, "Server terminated")))
-            .await?;
+    /// What the server does with each connection
+    #[arg(long, value_enum, default_value_t = Protocol::Greet)]
+    protocol: Protocol,
 
-        incoming_future = task::spawn(accept(listener));
-    }
+    /// Seconds that connections have to finish when the server is stopped, 0 cancels them
+    /// immediately (default 5)
+    #[arg(long)]
+    shutdown_timeout: Option<u64>,
+
+    /// Seconds that the server has to start listening (default 10)
+    #[arg(long)]
+    startup_timeout: Option<u64>,
+
+    /// Seconds that a connection can wait for the client's next message (default no limit)
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+
+    /// Most connections to handle at once, further clients wait until a connection ends
+    /// (default no limit)
+    #[arg(long)]
+    max_connections: Option<NonZeroUsize>,
+
+    /// Most connections to handle at once from a single IP address, further connections from
+    /// it are closed right away (default no limit)
+    #[arg(long)]
+    max_connections_per_ip: Option<NonZeroUsize>,
+
+    /// Address range that can connect, like 10.0.0.0/8, can be repeated (default every client
+    /// can connect)
+    #[arg(long = "allow")]
+    allowed_networks: Vec<Cidr>,
+
+    /// Address range that can't connect, can be repeated
+    #[arg(long = "deny")]
+    denied_networks: Vec<Cidr>,
+
+    /// PEM file with the TLS certificate chain, encrypts every connection
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file with the TLS private key
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Most detailed messages to log: off, error, warn, info, debug or trace
+    #[arg(long, default_value_t = LevelFilter::Info)]
+    log_level: LevelFilter
 }
 
-async fn accept(listener: TcpListener) -> Result<(TcpListener, TcpStream)> {
-    let (stream, _) = listener.accept().await?;
-    Ok((listener, stream))
+// The connection handlers that can be chosen with --protocol
+#[derive(Clone, Copy, ValueEnum)]
+enum Protocol {
+    /// Writes a greeting and closes the connection
+    Greet,
+    /// Reads and ignores everything that the client sends
+    Discard,
+    /// Writes everything that the client sends back to it
+    Echo,
+    /// Line-based commands: PING, ECHO <text> and QUIT
+    Line,
+    /// Writes every frame that the client sends back to it, frames start with a big-endian u32
+    /// length
+    FramedEcho,
+    /// HTTP/1.1: GET / returns a greeting, GET /stream returns a chunked response, and
+    /// GET /healthz and /readyz report the server's health
+    Http
 }
 
 #[async_std::main]
 async fn main() {
-    let (server_future, completion_token, cancelation_token) = run_server();
+    let args = Args::parse();
+
+    env_logger::Builder::new()
+        .filter_level(args.log_level)
+        .init();
+
+    // Listen for signals before starting the server, so that a SIGTERM during startup still
+    // stops the server cleanly
+    let signals = match SignalListener::new() {
+        Ok(signals) => signals,
+        Err(err) => {
+            println!("Failed to listen for signals: {}", err);
+            return;
+        }
+    };
+
+    let config = match load_config(&args).await {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Failed to read the configuration: {}", err);
+            return;
+        }
+    };
+
+    let mut server = match args.protocol {
+        Protocol::Greet => run_server(config, greet),
+        Protocol::Discard => run_server(config, discard),
+        Protocol::Echo => run_server(config, echo),
+        Protocol::Line => run_server(config, line_protocol()),
+        Protocol::FramedEcho => run_server(config, framed_echo),
+        Protocol::Http => run_server(config, http_protocol())
+    };
 
     println!("Server is starting");
 
     // Wait for the server to start
-    let local_addr = completion_token.await.unwrap();
+    let local_addrs = match server.ready().await {
+        Ok(local_addrs) => local_addrs,
+        Err(err) => {
+            println!("Server failed to start: {}", err);
+            return;
+        }
+    };
+
+    for local_addr in local_addrs {
+        println!("Server is listening at {}", local_addr);
+    }
 
-    println!("Server is listening at {}", local_addr);
-    println!("Push Return to stop the server");
+    println!("Press Ctrl+C or send SIGTERM to stop the server, send SIGHUP to reload");
 
-    let _ = std::io::stdin().read_line(&mut String::new()).unwrap();
+    // Signals and changes to the configuration file all arrive through events
+    let (events_sender, events) = channel::unbounded();
+    task::spawn(forward_signals(signals, events_sender.clone()));
+
+    if let Some(path) = &args.config {
+        task::spawn(watch_config_file(path.clone(), events_sender));
+    }
+
+    // Run until SIGINT or SIGTERM
+    loop {
+        match events.recv().await {
+            Ok(SignalAction::Shutdown) | Err(_) => break,
+            Ok(SignalAction::Reload) => reload(&server, &args).await
+        }
+    }
 
     // Stop the server
-    cancelation_token.cancel();
+    server.shutdown();
+
+    // join consumes the handle, so hold on to the metrics
+    let metrics = server.metrics().clone();
 
     // Wait for the server to shut down
-    let err = server_future.await.unwrap_err();
+    match server.join().await {
+        ServerOutcome::Canceled => println!("Server ended"),
+        ServerOutcome::Drained(stats) => println!("Server ended: {} connections drained, {} aborted", stats.drained, stats.aborted),
+        ServerOutcome::Failed(err) => println!("Server failed: {}", err)
+    }
+
+    if metrics.accept_failures() > 0 {
+        println!("Accepting failed {} times", metrics.accept_failures());
+    }
+
+    if metrics.denied_connections() > 0 {
+        println!("Denied {} connections from clients that aren't allowed to connect", metrics.denied_connections());
+    }
+
+    if metrics.rejected_connections() > 0 {
+        println!("Rejected {} connections from clients over their limits", metrics.rejected_connections());
+    }
+}
+
+// Combines the defaults, the configuration file and the command line, in that order
+async fn load_config(args: &Args) -> Result<ServerConfig> {
+    let mut config = ServerConfig::new()
+        .startup_timeout(DEFAULT_STARTUP_TIMEOUT)
+        .drain_timeout(DEFAULT_SHUTDOWN_TIMEOUT);
+
+    if let Some(path) = &args.config {
+        config = ConfigFile::load(path).await?.apply(config)?;
+    }
+
+    if !args.addresses.is_empty() {
+        config = config.clear_addresses();
+
+        for address in args.addresses.iter() {
+            config = config.address(*address);
+        }
+    }
+
+    if let Some(port) = args.port {
+        config = config.port(port);
+    }
+
+    if let Some(shutdown_timeout) = args.shutdown_timeout {
+        config = config.drain_timeout(Duration::from_secs(shutdown_timeout));
+    }
+
+    if let Some(startup_timeout) = args.startup_timeout {
+        config = config.startup_timeout(Duration::from_secs(startup_timeout));
+    }
+
+    if let Some(idle_timeout) = args.idle_timeout {
+        config = config.idle_timeout(Duration::from_secs(idle_timeout));
+    }
+
+    if let Some(max_connections) = args.max_connections {
+        config = config.max_connections(max_connections.get());
+    }
+
+    if let Some(max_connections_per_ip) = args.max_connections_per_ip {
+        config = config.max_connections_per_ip(max_connections_per_ip.get());
+    }
+
+    for network in args.allowed_networks.iter() {
+        config = config.allow_network(*network);
+    }
+
+    for network in args.denied_networks.iter() {
+        config = config.deny_network(*network);
+    }
+
+    if let (Some(tls_cert), Some(tls_key)) = (&args.tls_cert, &args.tls_key) {
+        config = config.tls(tls_cert, tls_key);
+    }
+
+    Ok(config)
+}
+
+// Called on SIGHUP, and when the configuration file changes
+async fn reload(server: &ServerHandle, args: &Args) {
+    if args.config.is_none() {
+        info!("Received SIGHUP, there is no configuration file to reload");
+        return;
+    }
+
+    match load_config(args).await {
+        Ok(config) => {
+            if server.reload(&config) {
+                info!("Reloaded the configuration");
+            } else {
+                warn!("Reloaded the configuration, changes to listeners require restarting the server");
+            }
+        },
+        Err(err) => warn!("Failed to reload the configuration: {}", err)
+    }
+}
+
+async fn forward_signals(mut signals: SignalListener, events: Sender<SignalAction>) {
+    loop {
+        let action = match signals.next().await {
+            Ok(action) => action,
+            Err(err) => {
+                // Without signals there is no way to stop the server cleanly, so stop it now
+                error!("Failed to wait for signals: {}", err);
+                SignalAction::Shutdown
+            }
+        };
+
+        if events.send(action).await.is_err() || action == SignalAction::Shutdown {
+            return;
+        }
+    }
+}
+
+// Polls the configuration file's modification time, and asks for a reload when it changes
+async fn watch_config_file(path: PathBuf, events: Sender<SignalAction>) {
+    let mut modified = modified_time(&path).await;
+
+    loop {
+        task::sleep(CONFIG_POLL_INTERVAL).await;
+
+        let current = modified_time(&path).await;
+
+        if current != modified {
+            modified = current;
+
+            if events.send(SignalAction::Reload).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).await.and_then(|metadata| metadata.modified()).ok()
+}
+
+// Writes a greeting to the client and then closes the connection
+async fn greet(mut stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> Result<()> {
+    info!("Accepted connection from {}", stream.peer_addr()?);
+
+    cancelable.allow_cancel(
+        timeouts.write(async {
+            stream.write_all(b"Hello from sync-tokens-example\n").await?;
+            stream.flush().await
+        }),
+        Err(server_terminated()))
+        .await
+}
+
+// A small example of LineProtocol
+fn line_protocol() -> LineProtocol {
+    LineProtocol::new()
+        .command("PING", |_| Reply::Line("PONG".to_string()))
+        .command("ECHO", |text| Reply::Line(text.to_string()))
+        .command("QUIT", |_| Reply::Close("BYE".to_string()))
+}
+
+// A small example of FramedStream
+async fn framed_echo(stream: ConnectionStream, cancelable: Cancelable, timeouts: ConnectionTimeouts) -> Result<()> {
+    let mut framed = FrameCodec::new().framed(stream, timeouts);
+
+    while let Some(frame) = framed.read_frame(&cancelable).await? {
+        framed.write_frame(&frame, &cancelable).await?;
+    }
+
+    Ok(())
+}
+
+// A small example of HttpProtocol
+fn http_protocol() -> HttpProtocol {
+    HttpProtocol::new()
+        .health_checks()
+        .route("GET", "/", |_| Response::text(200, "Hello from sync-tokens-example\n"))
+        .route("GET", "/stream", |_| {
+            // Each line is sent as its own chunk, as the task produces it
+            let (sender, chunks) = channel::bounded(1);
+
+            task::spawn(async move {
+                for count in 1..=5 {
+                    let line = format!("Chunk {}\n", count).into_bytes();
+
+                    if sender.send(line).await.is_err() {
+                        return;
+                    }
+
+                    task::sleep(Duration::from_millis(200)).await;
+                }
+            });
 
-    println!("Server ended: {}", err);
+            Response::stream(200, chunks)
+                .header("Content-Type", "text/plain; charset=utf-8")
+        })
 }
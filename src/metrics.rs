@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+
+// Counters that the server updates while it runs
+// Cloning is cheap, all clones share the same counters
+#[derive(Clone, Debug, Default)]
+pub struct ServerMetrics {
+    counters: Arc<Counters>
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    accept_failures: AtomicU64,
+    active_connections: AtomicUsize,
+    rejected_connections: AtomicU64,
+    denied_connections: AtomicU64
+}
+
+impl ServerMetrics {
+    // How many times accepting a connection failed, including transient failures that the
+    // server recovered from
+    pub fn accept_failures(&self) -> u64 {
+        self.counters.accept_failures.load(Ordering::Relaxed)
+    }
+
+    // How many connections are in flight right now
+    pub fn active_connections(&self) -> usize {
+        self.counters.active_connections.load(Ordering::Relaxed)
+    }
+
+    // How many connections were closed right after being accepted, because their peer was
+    // over its limits
+    pub fn rejected_connections(&self) -> u64 {
+        self.counters.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    // How many connections were closed right after being accepted, because their peer wasn't
+    // allowed to connect
+    pub fn denied_connections(&self) -> u64 {
+        self.counters.denied_connections.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_accept_failure(&self) {
+        self.counters.accept_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rejected_connection(&self) {
+        self.counters.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_denied_connection(&self) {
+        self.counters.denied_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connection_opened(&self) {
+        self.counters.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connection_closed(&self) {
+        self.counters.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
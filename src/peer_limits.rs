@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{ Arc, Mutex };
+use std::time::Instant;
+
+use crate::config::{ RateLimit, ReloadableConfig };
+
+// Peers are only forgotten when the table is pruned, which happens whenever it grows past
+// this many entries (or twice its size after the last prune)
+const MIN_PRUNE_SIZE: usize = 1024;
+
+// Enforces ServerConfig::max_connections_per_ip and ServerConfig::connection_rate_per_ip
+// Cloning is cheap, all clones share the same table of peers
+#[derive(Clone, Default)]
+pub(crate) struct PeerLimiter {
+    state: Arc<Mutex<LimiterState>>
+}
+
+#[derive(Default)]
+struct LimiterState {
+    peers: HashMap<IpAddr, PeerState>,
+    prune_at: usize
+}
+
+struct PeerState {
+    active: usize,
+    // A token bucket, each connection takes one token
+    tokens: f64,
+    refilled_at: Instant
+}
+
+// Why a peer's connection was rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PeerRejection {
+    TooManyConnections(usize),
+    RateLimited
+}
+
+// Counts a connection against its peer's limit until it's dropped
+pub(crate) struct PeerGuard {
+    // None for peers that are exempt from the limits
+    peer: Option<(IpAddr, Arc<Mutex<LimiterState>>)>
+}
+
+impl PeerLimiter {
+    // Checks whether a new connection from address is within the limits in config, and if so,
+    // counts it until the returned PeerGuard is dropped
+    pub fn admit(&self, address: IpAddr, config: &ReloadableConfig) -> Result<PeerGuard, PeerRejection> {
+        self.admit_at(address, config, Instant::now())
+    }
+
+    // Like admit, with the time passed in so that tests can control how much the buckets refill
+    fn admit_at(&self, address: IpAddr, config: &ReloadableConfig, now: Instant) -> Result<PeerGuard, PeerRejection> {
+        let address = address.to_canonical();
+        let unlimited = config.max_connections_per_ip.is_none() && config.connection_rate_per_ip.is_none();
+
+        if unlimited || config.exempt_networks.iter().any(|network| network.contains(address)) {
+            return Ok(PeerGuard {
+                peer: None
+            });
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.peers.len() >= state.prune_at.max(MIN_PRUNE_SIZE) {
+            state.prune(config.connection_rate_per_ip, now);
+        }
+
+        let peer = state.peers.entry(address).or_insert_with(|| PeerState {
+            active: 0,
+            tokens: config.connection_rate_per_ip.map_or(0.0, |rate_limit| rate_limit.burst as f64),
+            refilled_at: now
+        });
+
+        if let Some(max_connections_per_ip) = config.max_connections_per_ip {
+            if peer.active >= max_connections_per_ip {
+                return Err(PeerRejection::TooManyConnections(max_connections_per_ip));
+            }
+        }
+
+        if let Some(rate_limit) = config.connection_rate_per_ip {
+            peer.refill(rate_limit, now);
+
+            if peer.tokens < 1.0 {
+                return Err(PeerRejection::RateLimited);
+            }
+
+            peer.tokens -= 1.0;
+        }
+
+        peer.active += 1;
+
+        Ok(PeerGuard {
+            peer: Some((address, self.state.clone()))
+        })
+    }
+}
+
+impl LimiterState {
+    // Forgets peers that have no connections and whose bucket has refilled, since a new entry
+    // would be the same
+    fn prune(&mut self, rate_limit: Option<RateLimit>, now: Instant) {
+        self.peers.retain(|_, peer| {
+            if peer.active > 0 {
+                return true;
+            }
+
+            match rate_limit {
+                Some(rate_limit) => {
+                    peer.refill(rate_limit, now);
+                    peer.tokens < rate_limit.burst as f64
+                },
+                None => false
+            }
+        });
+
+        self.prune_at = self.peers.len() * 2;
+    }
+}
+
+impl PeerState {
+    fn refill(&mut self, rate_limit: RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate_limit.per_second).min(rate_limit.burst as f64);
+        self.refilled_at = now;
+    }
+}
+
+impl Drop for PeerGuard {
+    fn drop(&mut self) {
+        if let Some((address, state)) = self.peer.take() {
+            let mut state = state.lock().unwrap();
+
+            if let Some(peer) = state.peers.get_mut(&address) {
+                peer.active = peer.active.saturating_sub(1);
+            }
+        }
+    }
+}
+
+impl fmt::Display for PeerRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerRejection::TooManyConnections(max) => write!(f, "already has {} connections", max),
+            PeerRejection::RateLimited => write!(f, "is connecting too often")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use crate::cidr::test_helpers::cidr;
+    use crate::config::ServerConfig;
+
+    use super::*;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn limits_connections_per_peer() {
+        let limiter = PeerLimiter::default();
+        let config = ServerConfig::new().max_connections_per_ip(2).reloadable();
+
+        let first = limiter.admit(PEER, &config).unwrap();
+        let _second = limiter.admit(PEER, &config).unwrap();
+        assert_eq!(limiter.admit(PEER, &config).err(), Some(PeerRejection::TooManyConnections(2)));
+
+        // Other peers have their own count
+        let _other = limiter.admit(OTHER_PEER, &config).unwrap();
+
+        // Dropping a guard frees its place
+        drop(first);
+        assert!(limiter.admit(PEER, &config).is_ok());
+    }
+
+    #[test]
+    fn rate_limit_allows_a_burst_and_then_refills() {
+        let limiter = PeerLimiter::default();
+        let config = ServerConfig::new().connection_rate_per_ip(10.0, 2).reloadable();
+        let start = Instant::now();
+
+        // Ending a connection doesn't give its token back
+        assert!(limiter.admit_at(PEER, &config, start).is_ok());
+        assert!(limiter.admit_at(PEER, &config, start).is_ok());
+        assert_eq!(limiter.admit_at(PEER, &config, start).err(), Some(PeerRejection::RateLimited));
+
+        // One token every 100ms
+        let later = start + Duration::from_millis(150);
+        assert!(limiter.admit_at(PEER, &config, later).is_ok());
+        assert_eq!(limiter.admit_at(PEER, &config, later).err(), Some(PeerRejection::RateLimited));
+
+        // The half token left over counts towards the next one
+        let even_later = start + Duration::from_millis(250);
+        assert!(limiter.admit_at(PEER, &config, even_later).is_ok());
+    }
+
+    #[test]
+    fn refill_is_capped_at_the_burst() {
+        let rate_limit = RateLimit { per_second: 2.0, burst: 3 };
+        let start = Instant::now();
+
+        let mut peer = PeerState {
+            active: 0,
+            tokens: 0.0,
+            refilled_at: start
+        };
+
+        peer.refill(rate_limit, start + Duration::from_millis(500));
+        assert!((peer.tokens - 1.0).abs() < 1e-9);
+
+        peer.refill(rate_limit, start + Duration::from_secs(60));
+        assert!((peer.tokens - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exempt_networks_arent_limited() {
+        let limiter = PeerLimiter::default();
+        let config = ServerConfig::new()
+            .max_connections_per_ip(1)
+            .exempt_network(cidr("192.0.2.0/24"))
+            .reloadable();
+
+        let _first = limiter.admit(PEER, &config).unwrap();
+        assert!(limiter.admit(PEER, &config).is_ok());
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_share_the_ipv4_count() {
+        let limiter = PeerLimiter::default();
+        let config = ServerConfig::new().max_connections_per_ip(1).reloadable();
+
+        let _first = limiter.admit(PEER, &config).unwrap();
+        let mapped = IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped());
+        assert_eq!(limiter.admit(mapped, &config).err(), Some(PeerRejection::TooManyConnections(1)));
+    }
+
+    #[test]
+    fn pruning_forgets_idle_peers() {
+        let limiter = PeerLimiter::default();
+        let config = ServerConfig::new().max_connections_per_ip(1).reloadable();
+
+        let _active = limiter.admit(PEER, &config).unwrap();
+
+        // The table is pruned when the last of these is admitted
+        for n in 0..MIN_PRUNE_SIZE as u32 {
+            let address = IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n));
+            drop(limiter.admit(address, &config).unwrap());
+        }
+
+        // Only the peer with a connection is left, plus the one that was admitted last
+        let peers = limiter.state.lock().unwrap().peers.len();
+        assert_eq!(peers, 2);
+        assert_eq!(limiter.admit(PEER, &config).err(), Some(PeerRejection::TooManyConnections(1)));
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::{ Arc, Mutex };
+
+// Allows changing settings while the server is running, without restarting it
+// Works like CancelationToken and CompletionToken: the caller keeps the ReloadToken and calls
+// reload with new values; Reloadable is cloned to every task that needs the latest value
+pub struct ReloadToken<T> {
+    shared_state: Arc<Mutex<Arc<T>>>
+}
+
+pub struct Reloadable<T> {
+    shared_state: Arc<Mutex<Arc<T>>>
+}
+
+impl<T> ReloadToken<T> {
+    pub fn new(initial: T) -> (ReloadToken<T>, Reloadable<T>) {
+        let shared_state = Arc::new(Mutex::new(Arc::new(initial)));
+
+        let reload_token = ReloadToken {
+            shared_state: shared_state.clone()
+        };
+
+        let reloadable = Reloadable {
+            shared_state
+        };
+
+        (reload_token, reloadable)
+    }
+
+    // Replaces the value, tasks see it the next time they call Reloadable::get
+    pub fn reload(&self, value: T) {
+        *self.shared_state.lock().unwrap() = Arc::new(value);
+    }
+
+    // The current value
+    pub fn get(&self) -> Arc<T> {
+        self.shared_state.lock().unwrap().clone()
+    }
+}
+
+impl<T> Reloadable<T> {
+    // The current value
+    // Tasks should call this each time they need the value, instead of holding on to it
+    pub fn get(&self) -> Arc<T> {
+        self.shared_state.lock().unwrap().clone()
+    }
+}
+
+// Implemented by hand because deriving Clone would require T: Clone
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Reloadable {
+            shared_state: self.shared_state.clone()
+        }
+    }
+}
@@ -0,0 +1,460 @@
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+use async_std::channel;
+use async_std::future;
+use async_std::io::{ Error, ErrorKind, Result };
+use async_std::net::{ TcpListener, TcpStream, SocketAddr };
+use async_std::task;
+
+use sync_tokens::cancelation_token::{ Cancelable, CancelationToken };
+use sync_tokens::completion_token::{ Completable, CompletionToken };
+
+use log::{ debug, info, log, warn, Level };
+use socket2::{ Domain, Protocol, Socket, Type };
+
+use crate::config::{ ReloadableConfig, ServerConfig };
+use crate::connection::{ ConnectionHandler, ConnectionSlot, ConnectionTracker, ShutdownStats };
+use crate::handle::ServerHandle;
+use crate::metrics::ServerMetrics;
+use crate::peer_limits::{ PeerGuard, PeerLimiter };
+use crate::reload_token::{ Reloadable, ReloadToken };
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+use crate::tls::TlsTermination;
+
+// How long to wait before accepting again after a transient failure
+// The delay doubles with each consecutive failure, up to MAX_ACCEPT_BACKOFF
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+// While the connection limit is reached, how often the accept loop checks the limit again even
+// if no connection ends, so that raising it with ServerHandle::reload takes effect
+const CONNECTION_LIMIT_RECHECK: Duration = Duration::from_secs(1);
+
+// How the server stopped, returned from ServerHandle::join
+#[derive(Debug)]
+pub enum ServerOutcome {
+    // The server was canceled, and in-flight connections were canceled along with it
+    Canceled,
+    // The server was canceled, and in-flight connections were given a chance to finish
+    Drained(ShutdownStats),
+    // The server stopped because of an error, like failing to bind or accept
+    Failed(Error)
+}
+
+// Starts running a server on a background task
+// config controls where the server listens and how it shuts down
+// handler is called on a new task for each accepted connection
+// The returned ServerHandle is used to wait for the server to start listening, and to stop it
+pub fn run_server<H: ConnectionHandler>(config: ServerConfig, handler: H) -> ServerHandle {
+    // This CompletionToken allows the caller to wait until the server is actually listening
+    // The caller gets completion_token, which it can await on
+    // completable is used to signal to completion_token
+    let (completion_token, completable) = CompletionToken::new();
+
+    // This CancelationToken allows the caller to stop the server
+    // The caller gets cancelation_token
+    // cancelable is used to allow canceling a call to await
+    let (cancelation_token, cancelable) = CancelationToken::new();
+
+    // This ReloadToken allows the caller to change settings while the server is running
+    // The caller gets reload_token
+    // reloadable is used to read the latest settings
+    let (reload_token, reloadable) = ReloadToken::new(config.reloadable());
+
+    // Set by ServerHandle::shutdown_graceful, and read once the server is canceled
+    // It's kept apart from the reloadable settings so that a reload can't undo it
+    let (graceful_token, graceful) = ReloadToken::new(None);
+
+    let metrics = ServerMetrics::default();
+    let server_metrics = metrics.clone();
+
+    // ServerHandle::reload compares new settings to these
+    let config_for_handle = config.clone();
+
+    // The server is started on a background task, and the future returned
+    let server_future = task::spawn(async move {
+        run_server_int(config, Arc::new(handler), reloadable, graceful, server_metrics, completable, cancelable)
+            .await
+            .unwrap_or_else(ServerOutcome::Failed)
+    });
+
+    ServerHandle::new(server_future, completion_token, cancelation_token, reload_token, graceful_token, config_for_handle, metrics)
+}
+
+async fn run_server_int<H: ConnectionHandler>(config: ServerConfig, handler: Arc<H>, reloadable: Reloadable<ReloadableConfig>, graceful: Reloadable<Option<Duration>>, metrics: ServerMetrics, completable: Completable<Result<Vec<SocketAddr>>>, cancelable: Cancelable) -> Result<ServerOutcome> {
+
+    // Binding happens on a blocking task so that it can be timed out
+    // If it takes too long, the server fails to start. Any listeners that are bound afterwards
+    // are dropped when the blocking task finishes.
+    // The TLS certificate is read first, so that nothing is bound if it's invalid
+    let bind_config = config.clone();
+    let binding = task::spawn_blocking(move || {
+        let tls = bind_config.tls.as_ref().map(TlsTermination::load).transpose()?;
+        Ok((bind_all(&bind_config)?, tls))
+    });
+
+    let bound = match config.startup_timeout {
+        Some(startup_timeout) => future::timeout(startup_timeout, binding)
+            .await
+            .unwrap_or_else(|_| Err(Error::new(ErrorKind::TimedOut, "Server did not start listening in time"))),
+        None => binding.await
+    };
+
+    let (listeners, tls) = match bound {
+        Ok((listeners, tls)) => (listeners
+            .into_iter()
+            .map(TcpListener::from)
+            .collect::<Vec<TcpListener>>(), tls),
+        Err(err) => {
+            // Callers wait on the CompletionToken to find out if the server started, so the
+            // error is reported there as well as through the JoinHandle
+            // (Error can't be cloned, so a copy is made with the same kind and message)
+            completable.complete(Err(Error::new(err.kind(), err.to_string())));
+            return Err(err);
+        }
+    };
+
+    // Inform that the server is listening
+    let local_addrs = listeners
+        .iter()
+        .map(|listener| listener.local_addr())
+        .collect();
+    completable.complete(local_addrs);
+    handler.started();
+
+    // Each connection gets its own Cancelable, which is canceled when the server stops
+    let connections = ConnectionTracker::default();
+
+    // Each listener runs its own accept loop, with its own CancelationToken that is canceled
+    // when the server is canceled
+    // If an accept loop fails, it sends its error through errors_sender
+    let (errors_sender, errors_receiver) = channel::unbounded();
+    let mut accept_loops = Vec::new();
+
+    let accept_state = AcceptState {
+        handler: handler.clone(),
+        connections: connections.clone(),
+        peer_limiter: PeerLimiter::default(),
+        reloadable: reloadable.clone(),
+        tls: tls.clone(),
+        metrics,
+        errors_sender
+    };
+
+    for listener in listeners {
+        let (listener_cancelation_token, listener_cancelable) = CancelationToken::new();
+        let accept_loop_future = task::spawn(accept_loop(
+            listener,
+            listener_cancelable,
+            accept_state.clone()));
+
+        accept_loops.push((listener_cancelation_token, accept_loop_future));
+    }
+
+    // Wait for either the CancelationToken to be canceled, or for an accept loop to fail
+    // When the CancelationToken is canceled, None is returned
+    let failed = cancelable.allow_cancel(
+        async { errors_receiver.recv().await.ok() },
+        None)
+        .await;
+
+    // Stop all of the listeners, and wait for them so that nothing is accepted while draining
+    for (listener_cancelation_token, _) in accept_loops.iter() {
+        listener_cancelation_token.cancel();
+    }
+
+    for (_, accept_loop_future) in accept_loops {
+        accept_loop_future.await;
+    }
+
+    handler.shutdown_started();
+
+    if let Some(tls) = &tls {
+        tls.shutdown_started();
+    }
+
+    if let Some(err) = failed {
+        // Stop all in-flight connections along with the server
+        connections.cancel_all();
+        return Err(err);
+    }
+
+    // A timeout passed to ServerHandle::shutdown_graceful takes precedence over the settings
+    let drain_timeout = graceful.get().or(reloadable.get().drain_timeout);
+
+    match drain_timeout {
+        // The server no longer accepts connections. Give the in-flight connections a chance to
+        // finish, and then cancel whatever is left
+        Some(drain_timeout) if drain_timeout > Duration::from_secs(0) => Ok(ServerOutcome::Drained(connections.drain(drain_timeout).await)),
+        _ => {
+            connections.cancel_all();
+            Ok(ServerOutcome::Canceled)
+        }
+    }
+}
+
+// Shared by every listener's accept loop
+struct AcceptState<H> {
+    handler: Arc<H>,
+    connections: ConnectionTracker,
+    // Counts connections from each peer across all listeners
+    peer_limiter: PeerLimiter,
+    reloadable: Reloadable<ReloadableConfig>,
+    tls: Option<TlsTermination>,
+    metrics: ServerMetrics,
+    errors_sender: channel::Sender<Error>
+}
+
+// Implemented by hand because deriving Clone would require H: Clone
+impl<H> Clone for AcceptState<H> {
+    fn clone(&self) -> Self {
+        AcceptState {
+            handler: self.handler.clone(),
+            connections: self.connections.clone(),
+            peer_limiter: self.peer_limiter.clone(),
+            reloadable: self.reloadable.clone(),
+            tls: self.tls.clone(),
+            metrics: self.metrics.clone(),
+            errors_sender: self.errors_sender.clone()
+        }
+    }
+}
+
+// Accepts connections from a single listener until cancelable is canceled
+// The listener stays on this task; each call to accept is raced against the CancelationToken
+async fn accept_loop<H: ConnectionHandler>(listener: TcpListener, cancelable: Cancelable, state: AcceptState<H>) {
+    let AcceptState { handler, connections, peer_limiter, reloadable, tls, metrics, errors_sender } = state;
+    let mut backoff = MIN_ACCEPT_BACKOFF;
+
+    loop {
+        // Wait for either an incoming socket or for the CancelationToken to be canceled.
+        // When the CancelationToken is canceled, None is returned
+        let accepted = cancelable.allow_cancel(
+            async { Some(listener.accept().await) },
+            None)
+            .await;
+
+        let (stream, peer_addr) = match accepted {
+            Some(Ok(accepted)) => accepted,
+            Some(Err(err)) => {
+                metrics.record_accept_failure();
+
+                if !is_transient(&err) {
+                    // The server stops when any listener fails
+                    let _ = errors_sender.send(err).await;
+                    return;
+                }
+
+                // Give the system a chance to recover, for example to free file descriptors,
+                // before accepting again. The wait is canceled along with the server.
+                warn!("Accept failed, retrying in {:?}: {}", backoff, err);
+
+                let canceled = cancelable.allow_cancel(
+                    async { task::sleep(backoff).await; false },
+                    true)
+                    .await;
+
+                if canceled {
+                    return;
+                }
+
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            },
+            None => return
+        };
+
+        backoff = MIN_ACCEPT_BACKOFF;
+
+        // Peers that aren't allowed, or are over their limits, are disconnected before a
+        // handler is spawned. Dropping the stream closes it.
+        let reloadable_config = reloadable.get();
+
+        if !reloadable_config.permits(peer_addr.ip()) {
+            metrics.record_denied_connection();
+            debug!("Denied connection from {}", peer_addr);
+            continue;
+        }
+
+        let peer_guard = match peer_limiter.admit(peer_addr.ip(), &reloadable_config) {
+            Ok(peer_guard) => peer_guard,
+            Err(rejection) => {
+                metrics.record_rejected_connection();
+                debug!("Rejected connection from {}, it {}", peer_addr, rejection);
+                continue;
+            }
+        };
+
+        // Connections get the timeouts that are configured when they are accepted
+        let timeouts = ConnectionTimeouts::new(&reloadable_config, Instant::now());
+
+        // Only accepted connections count towards the limit, so an idle listener doesn't use
+        // up a slot. If the limit is reached, this connection waits for a slot here, and
+        // nothing more is accepted from this listener in the meantime.
+        // When the CancelationToken is canceled, None is returned
+        let slot = match reserve_slot(&listener, &cancelable, &connections, &reloadable).await {
+            Some(slot) => slot,
+            None => return
+        };
+
+        // Handle the connection on its own task so that the server can keep accepting
+        spawn_connection(&handler, &tls, slot, peer_guard, timeouts, &metrics, stream);
+    }
+}
+
+// Waits until there are fewer in-flight connections than ServerConfig::max_connections, and
+// reserves a slot for the connection that was just accepted
+// While waiting, nothing more is accepted from the listener, so other clients queue up in its
+// backlog
+async fn reserve_slot(listener: &TcpListener, cancelable: &Cancelable, connections: &ConnectionTracker, reloadable: &Reloadable<ReloadableConfig>) -> Option<ConnectionSlot> {
+    let mut paused = false;
+
+    loop {
+        let max_connections = reloadable.get().max_connections;
+
+        let slot_waiter = match connections.try_reserve(max_connections) {
+            Ok(slot) => {
+                if paused {
+                    info!("Resuming accepting on {}", local_addr_string(listener));
+                }
+
+                return Some(slot);
+            },
+            Err(slot_waiter) => slot_waiter
+        };
+
+        if !paused {
+            info!("Reached the limit of {} connections, pausing accepting on {}", max_connections.unwrap_or(0), local_addr_string(listener));
+            paused = true;
+        }
+
+        let canceled = cancelable.allow_cancel(
+            async { let _ = future::timeout(CONNECTION_LIMIT_RECHECK, slot_waiter.wait()).await; false },
+            true)
+            .await;
+
+        if canceled {
+            return None;
+        }
+    }
+}
+
+fn local_addr_string(listener: &TcpListener) -> String {
+    match listener.local_addr() {
+        Ok(local_addr) => local_addr.to_string(),
+        Err(_) => "a listener".to_string()
+    }
+}
+
+// Whether an accept error only affects a single connection, or is caused by a temporary
+// shortage of resources, so that the listener can keep accepting
+fn is_transient(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock => true,
+        _ => is_resource_exhausted(err)
+    }
+}
+
+// Running out of file descriptors or memory
+#[cfg(unix)]
+fn is_resource_exhausted(err: &Error) -> bool {
+    match err.raw_os_error() {
+        Some(code) => code == libc::EMFILE || code == libc::ENFILE || code == libc::ENOBUFS || code == libc::ENOMEM,
+        None => false
+    }
+}
+
+#[cfg(not(unix))]
+fn is_resource_exhausted(_err: &Error) -> bool {
+    false
+}
+
+// Creates a listening socket for every address in config
+fn bind_all(config: &ServerConfig) -> Result<Vec<std::net::TcpListener>> {
+    config.socket_addrs()
+        .iter()
+        .map(|socket_addr| bind(config, *socket_addr))
+        .collect()
+}
+
+// Creates the listening socket
+// socket2 is used because the standard library doesn't allow setting the backlog or
+// dual-stack mode
+fn bind(config: &ServerConfig, socket_addr: SocketAddr) -> Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(socket_addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if socket_addr.is_ipv6() {
+        if let Some(dual_stack) = config.dual_stack {
+            socket.set_only_v6(!dual_stack)?;
+        }
+    }
+
+    // Matches what the standard library does, so that a restarted server can bind right away
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+
+    socket.bind(&socket_addr.into())?;
+    socket.listen(config.backlog)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
+
+fn spawn_connection<H: ConnectionHandler>(handler: &Arc<H>, tls: &Option<TlsTermination>, slot: ConnectionSlot, peer_guard: PeerGuard, timeouts: ConnectionTimeouts, metrics: &ServerMetrics, stream: TcpStream) {
+    let peer_addr = stream.peer_addr();
+    let (guard, cancelable) = slot.register();
+
+    // The TLS handshake happens on the connection's task, so that a slow client doesn't hold
+    // up accepting
+    let handler = handler.clone();
+    let tls = tls.clone();
+    let connection_future = async move {
+        let stream = match tls {
+            Some(tls) => tls.accept(stream, &cancelable, &timeouts).await?,
+            None => ConnectionStream::plain(stream)
+        };
+
+        handler.handle(stream.clone(), cancelable, timeouts).await?;
+
+        // Ends a TLS session with close_notify, so that the client can tell that the response
+        // wasn't cut off. When the handler fails, the connection is just dropped instead, since
+        // it didn't end cleanly.
+        match timeouts.write(stream.close()).await {
+            // The client already went away, so there is nobody left to tell
+            Err(err) if matches!(err.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::NotConnected) => Ok(()),
+            closed => closed
+        }
+    };
+
+    let metrics = metrics.clone();
+    metrics.record_connection_opened();
+
+    task::spawn(async move {
+        // The guards unregister the connection when the task ends
+        let _guard = guard;
+        let _peer_guard = peer_guard;
+
+        // Timing out and being canceled along with the server are how connections routinely
+        // end, so only other errors are warned about
+        if let Err(err) = timeouts.lifetime(connection_future).await {
+            let level = match err.kind() {
+                ErrorKind::TimedOut | ErrorKind::Interrupted => Level::Debug,
+                _ => Level::Warn
+            };
+
+            match peer_addr {
+                Ok(peer_addr) => log!(level, "Connection from {} ended: {}", peer_addr, err),
+                Err(_) => log!(level, "Connection ended: {}", err)
+            }
+        }
+
+        metrics.record_connection_closed();
+    });
+}
@@ -0,0 +1,46 @@
+use std::io::{ Error, ErrorKind };
+
+use async_signal::{ Signal, Signals };
+use async_std::io::Result;
+use async_std::stream::StreamExt;
+
+// What the process should do in response to a signal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignalAction {
+    // SIGINT or SIGTERM
+    Shutdown,
+    // SIGHUP
+    Reload
+}
+
+// Listens for the signals that a daemon is expected to handle
+// Once this is created, the process is no longer terminated by SIGINT, SIGTERM or SIGHUP
+// On Windows only Ctrl+C (SIGINT) is supported, so there is no way to ask for a reload
+pub struct SignalListener {
+    signals: Signals
+}
+
+impl SignalListener {
+    pub fn new() -> Result<SignalListener> {
+        #[cfg(unix)]
+        let signals = Signals::new([Signal::Int, Signal::Term, Signal::Hup])?;
+
+        #[cfg(not(unix))]
+        let signals = Signals::new([Signal::Int])?;
+
+        Ok(SignalListener { signals })
+    }
+
+    // Waits for the next signal
+    pub async fn next(&mut self) -> Result<SignalAction> {
+        loop {
+            match self.signals.next().await {
+                Some(Ok(Signal::Int)) | Some(Ok(Signal::Term)) => return Ok(SignalAction::Shutdown),
+                Some(Ok(Signal::Hup)) => return Ok(SignalAction::Reload),
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Err(err),
+                None => return Err(Error::new(ErrorKind::BrokenPipe, "No more signals will be received"))
+            }
+        }
+    }
+}
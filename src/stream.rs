@@ -0,0 +1,134 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{ Arc, Mutex };
+use std::task::{ Context, Poll };
+
+use async_std::future;
+use async_std::io::{ Read, Result, Write };
+use async_std::net::TcpStream;
+
+use futures_rustls::server::TlsStream;
+
+// An accepted connection, passed to ConnectionHandler::handle
+// Either a plain TcpStream, or a TLS session on top of one when the server is configured with
+// ServerConfig::tls. Like TcpStream, it can be read from and written to through shared
+// references, and clones share the same connection.
+#[derive(Clone)]
+pub struct ConnectionStream {
+    inner: StreamInner
+}
+
+#[derive(Clone)]
+enum StreamInner {
+    Plain(TcpStream),
+    Tls {
+        // Locked for the duration of a single poll, so a reader and a writer on different
+        // tasks never wait on each other
+        session: Arc<Mutex<TlsStream<TcpStream>>>,
+        // Kept outside of the session so that the addresses can be read without locking it
+        tcp_stream: TcpStream
+    }
+}
+
+impl ConnectionStream {
+    pub(crate) fn plain(tcp_stream: TcpStream) -> ConnectionStream {
+        ConnectionStream {
+            inner: StreamInner::Plain(tcp_stream)
+        }
+    }
+
+    pub(crate) fn tls(session: TlsStream<TcpStream>) -> ConnectionStream {
+        let tcp_stream = session.get_ref().0.clone();
+
+        ConnectionStream {
+            inner: StreamInner::Tls {
+                session: Arc::new(Mutex::new(session)),
+                tcp_stream
+            }
+        }
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.tcp_stream().peer_addr()
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.tcp_stream().local_addr()
+    }
+
+    // Whether the connection is encrypted with TLS
+    pub fn is_tls(&self) -> bool {
+        matches!(self.inner, StreamInner::Tls { .. })
+    }
+
+    // Flushes anything that is still buffered and closes the writing half of the connection
+    // For TLS, this sends close_notify first, so that the client can tell that nothing was
+    // cut off. run_server calls this when a handler returns successfully.
+    pub async fn close(&self) -> Result<()> {
+        let mut writer = self;
+        future::poll_fn(|cx| Pin::new(&mut writer).poll_close(cx)).await
+    }
+
+    // The underlying TcpStream, for socket options like set_nodelay
+    // Reading from or writing to it directly bypasses TLS
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match &self.inner {
+            StreamInner::Plain(tcp_stream) => tcp_stream,
+            StreamInner::Tls { tcp_stream, .. } => tcp_stream
+        }
+    }
+}
+
+impl Read for &ConnectionStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        match &self.inner {
+            StreamInner::Plain(tcp_stream) => Pin::new(&mut &*tcp_stream).poll_read(cx, buf),
+            StreamInner::Tls { session, .. } => Pin::new(&mut *session.lock().unwrap()).poll_read(cx, buf)
+        }
+    }
+}
+
+impl Write for &ConnectionStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        match &self.inner {
+            StreamInner::Plain(tcp_stream) => Pin::new(&mut &*tcp_stream).poll_write(cx, buf),
+            StreamInner::Tls { session, .. } => Pin::new(&mut *session.lock().unwrap()).poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match &self.inner {
+            StreamInner::Plain(tcp_stream) => Pin::new(&mut &*tcp_stream).poll_flush(cx),
+            StreamInner::Tls { session, .. } => Pin::new(&mut *session.lock().unwrap()).poll_flush(cx)
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match &self.inner {
+            StreamInner::Plain(tcp_stream) => Pin::new(&mut &*tcp_stream).poll_close(cx),
+            StreamInner::Tls { session, .. } => Pin::new(&mut *session.lock().unwrap()).poll_close(cx)
+        }
+    }
+}
+
+// Reading and writing through an owned ConnectionStream works the same way as through a
+// shared reference
+impl Read for ConnectionStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut &*self).poll_read(cx, buf)
+    }
+}
+
+impl Write for ConnectionStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut &*self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut &*self).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut &*self).poll_close(cx)
+    }
+}
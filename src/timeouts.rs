@@ -0,0 +1,119 @@
+use std::future::Future;
+use std::time::{ Duration, Instant };
+
+use async_std::future;
+use async_std::io::{ Error, ErrorKind, Result };
+
+use crate::config::ReloadableConfig;
+
+// Used when ServerConfig::tls_handshake_timeout isn't set
+const DEFAULT_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Deadlines for a single connection, from ServerConfig::idle_timeout, read_timeout,
+// write_timeout and max_connection_lifetime
+// Works like Cancelable::allow_cancel: handlers wrap each read or write, and the wrapped future
+// is raced against the deadline. When the deadline passes first, the future is dropped and a
+// TimedOut error is returned.
+//
+// run_server passes each connection's timeouts to its handler, along with the Cancelable.
+//
+// let read = timeouts.idle(stream.read(&mut buf)).await?;
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionTimeouts {
+    idle: Option<Duration>,
+    read: Option<Duration>,
+    write: Option<Duration>,
+    // Only used by run_server, when the server is configured with TLS
+    handshake: Duration,
+    // When the connection's lifetime ends, every wait is cut short at this point
+    expires_at: Option<Instant>
+}
+
+impl ConnectionTimeouts {
+    // Waits for the client to start sending its next message or request
+    pub async fn idle<F, T>(&self, io: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>
+    {
+        self.race(self.idle, io, "The connection was idle for too long").await
+    }
+
+    // Reads the rest of a message or request that the client has started sending
+    pub async fn read<F, T>(&self, io: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>
+    {
+        self.race(self.read, io, "Reading from the client took too long").await
+    }
+
+    // Writes to the client
+    pub async fn write<F, T>(&self, io: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>
+    {
+        self.race(self.write, io, "Writing to the client took too long").await
+    }
+
+    pub(crate) fn new(config: &ReloadableConfig, accepted_at: Instant) -> ConnectionTimeouts {
+        ConnectionTimeouts {
+            idle: config.idle_timeout,
+            read: config.read_timeout,
+            write: config.write_timeout,
+            handshake: config.tls_handshake_timeout.unwrap_or(DEFAULT_TLS_HANDSHAKE_TIMEOUT),
+            expires_at: config.max_connection_lifetime.map(|lifetime| accepted_at + lifetime)
+        }
+    }
+
+    // Performs the TLS handshake
+    pub(crate) async fn handshake<F, T>(&self, io: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>
+    {
+        self.race(Some(self.handshake), io, "The TLS handshake took too long").await
+    }
+
+    // Races the whole connection against its lifetime, so that handlers which don't use these
+    // timeouts still can't keep a connection open forever
+    pub(crate) async fn lifetime<F, T>(&self, connection: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>
+    {
+        self.race(None, connection, lifetime_message()).await
+    }
+
+    async fn race<F, T>(&self, timeout: Option<Duration>, io: F, message: &str) -> Result<T>
+    where
+        F: Future<Output = Result<T>>
+    {
+        let remaining_lifetime = self.expires_at.map(|expires_at| expires_at.saturating_duration_since(Instant::now()));
+
+        // Whichever ends first, the timeout for this kind of I/O or the connection's lifetime
+        let (timeout, message) = match (timeout, remaining_lifetime) {
+            (Some(timeout), Some(remaining_lifetime)) if remaining_lifetime < timeout => (remaining_lifetime, lifetime_message()),
+            (Some(timeout), _) => (timeout, message),
+            (None, Some(remaining_lifetime)) => (remaining_lifetime, lifetime_message()),
+            (None, None) => return io.await
+        };
+
+        future::timeout(timeout, io)
+            .await
+            .unwrap_or_else(|_| Err(Error::new(ErrorKind::TimedOut, message)))
+    }
+}
+
+// No timeouts, for calling a handler outside of run_server
+impl Default for ConnectionTimeouts {
+    fn default() -> Self {
+        ConnectionTimeouts {
+            idle: None,
+            read: None,
+            write: None,
+            handshake: DEFAULT_TLS_HANDSHAKE_TIMEOUT,
+            expires_at: None
+        }
+    }
+}
+
+fn lifetime_message() -> &'static str {
+    "The connection reached its maximum lifetime"
+}
@@ -0,0 +1,85 @@
+use std::io::{ Error, ErrorKind };
+use std::path::Path;
+use std::sync::Arc;
+
+use async_std::io::Result;
+use async_std::net::TcpStream;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use futures_rustls::TlsAcceptor;
+use futures_rustls::rustls;
+use rustls_pki_types::{ CertificateDer, PrivateKeyDer };
+use rustls_pki_types::pem::PemObject;
+
+use crate::config::TlsSettings;
+use crate::connection::{ server_terminated, ConnectionTracker };
+use crate::stream::ConnectionStream;
+use crate::timeouts::ConnectionTimeouts;
+
+// Performs the server side of the TLS handshake for every accepted connection
+// Cloning is cheap, all clones share the same certificate and handshakes
+#[derive(Clone)]
+pub(crate) struct TlsTermination {
+    acceptor: TlsAcceptor,
+    // Canceled when the server starts shutting down, so that pending handshakes don't wait for
+    // the drain timeout
+    handshakes: ConnectionTracker
+}
+
+impl TlsTermination {
+    // Reads the certificate chain and private key
+    // Reads files, so it's called on the same blocking task that binds the listeners
+    pub fn load(settings: &TlsSettings) -> Result<TlsTermination> {
+        Ok(TlsTermination {
+            acceptor: load_acceptor(settings)?,
+            handshakes: ConnectionTracker::default()
+        })
+    }
+
+    // Performs the handshake, raced against the handshake timeout, the connection's
+    // Cancelable, and the server shutting down
+    pub async fn accept(&self, tcp_stream: TcpStream, cancelable: &Cancelable, timeouts: &ConnectionTimeouts) -> Result<ConnectionStream> {
+        let (_handshake_guard, handshake_cancelable) = self.handshakes.register();
+
+        let session = handshake_cancelable.allow_cancel(
+            cancelable.allow_cancel(
+                timeouts.handshake(self.acceptor.accept(tcp_stream)),
+                Err(server_terminated())),
+            Err(server_terminated()))
+            .await?;
+
+        Ok(ConnectionStream::tls(session))
+    }
+
+    pub fn shutdown_started(&self) {
+        self.handshakes.cancel_all();
+    }
+}
+
+fn load_acceptor(settings: &TlsSettings) -> Result<TlsAcceptor> {
+    let cert_chain = CertificateDer::pem_file_iter(&settings.cert_path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|err| pem_error(&settings.cert_path, err))?;
+
+    if cert_chain.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} doesn't contain any certificates", settings.cert_path.display())));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(&settings.key_path)
+        .map_err(|err| pem_error(&settings.key_path, err))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, key)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Invalid TLS certificate or key: {}", err)))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn pem_error(path: &Path, err: rustls_pki_types::pem::Error) -> Error {
+    match err {
+        rustls_pki_types::pem::Error::Io(err) => Error::new(err.kind(), format!("Failed to read {}: {}", path.display(), err)),
+        err => Error::new(ErrorKind::InvalidData, format!("Failed to parse {}: {}", path.display(), err))
+    }
+}
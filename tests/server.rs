@@ -0,0 +1,81 @@
+// Runs plain TCP servers on the loopback interface
+
+use std::net::{ IpAddr, Ipv4Addr, SocketAddr };
+use std::time::{ Duration, Instant };
+
+use async_std::future;
+use async_std::io::prelude::*;
+use async_std::net::TcpStream;
+use async_std::task;
+
+use sync_tokens_example::config::ServerConfig;
+use sync_tokens_example::handlers::echo::echo;
+use sync_tokens_example::server::run_server;
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// How long a test waits for something that should happen right away
+const PROMPTLY: Duration = Duration::from_secs(5);
+
+// Sends a message and waits up to timeout for it to be echoed back
+async fn echoes(stream: &mut TcpStream, timeout: Duration) -> bool {
+    stream.write_all(b"ping").await.unwrap();
+
+    let mut reply = [0u8; 4];
+    match future::timeout(timeout, stream.read_exact(&mut reply)).await {
+        Ok(read) => {
+            read.unwrap();
+            assert_eq!(&reply, b"ping");
+            true
+        },
+        Err(_) => false
+    }
+}
+
+// Waits until nothing is listening at local_addr
+async fn wait_until_closed(local_addr: SocketAddr) {
+    let started = Instant::now();
+
+    while TcpStream::connect(local_addr).await.is_ok() {
+        assert!(started.elapsed() < PROMPTLY, "The server is still listening");
+        task::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[async_std::test]
+async fn dropping_a_join_cancels_the_server() {
+    let mut server = run_server(ServerConfig::new().address(LOCALHOST), echo).cancel_on_drop();
+    let local_addr = server.ready().await.unwrap()[0];
+
+    // The handle is dropped along with the join that timed out
+    assert!(future::timeout(Duration::from_millis(50), server.join()).await.is_err());
+
+    wait_until_closed(local_addr).await;
+}
+
+#[async_std::test]
+async fn idle_listeners_dont_use_up_the_connection_limit() {
+    let config = ServerConfig::new()
+        .listen(SocketAddr::new(LOCALHOST, 0))
+        .listen(SocketAddr::new(LOCALHOST, 0))
+        .max_connections(1);
+
+    let mut server = run_server(config, echo).cancel_on_drop();
+    let local_addrs = server.ready().await.unwrap();
+
+    // Either listener can take the only slot while the other one is idle
+    let mut second = TcpStream::connect(local_addrs[1]).await.unwrap();
+    assert!(echoes(&mut second, PROMPTLY).await);
+
+    // The limit still holds across both listeners
+    let mut first = TcpStream::connect(local_addrs[0]).await.unwrap();
+    assert!(!echoes(&mut first, Duration::from_millis(200)).await);
+    assert_eq!(server.metrics().active_connections(), 1);
+
+    // The waiting connection is handled once the slot is freed
+    drop(second);
+
+    let mut reply = [0u8; 4];
+    future::timeout(PROMPTLY, first.read_exact(&mut reply)).await.unwrap().unwrap();
+    assert_eq!(&reply, b"ping");
+}
@@ -0,0 +1,230 @@
+// Runs servers with TLS on the loopback interface, with a certificate generated for each test
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{ IpAddr, Ipv4Addr, SocketAddr };
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+use async_std::future;
+use async_std::io::prelude::*;
+use async_std::net::TcpStream;
+use async_std::task;
+
+use futures_rustls::TlsConnector;
+use futures_rustls::rustls::{ ClientConfig, RootCertStore };
+use rustls_pki_types::ServerName;
+
+use sync_tokens::cancelation_token::Cancelable;
+
+use sync_tokens_example::config::ServerConfig;
+use sync_tokens_example::handlers::echo::echo;
+use sync_tokens_example::server::{ run_server, ServerOutcome };
+use sync_tokens_example::stream::ConnectionStream;
+use sync_tokens_example::timeouts::ConnectionTimeouts;
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// How long a test waits for something that should happen right away
+const PROMPTLY: Duration = Duration::from_secs(5);
+
+// PEM files for a self-signed localhost certificate, deleted when dropped
+struct TestCert {
+    dir: PathBuf,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    client_config: Arc<ClientConfig>
+}
+
+impl TestCert {
+    // name keeps the files of tests that run at the same time apart
+    fn generate(name: &str) -> TestCert {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("sync-tokens-example-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        fs::write(&cert_path, certified.cert.pem()).unwrap();
+        fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+        // The client only trusts the generated certificate
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        TestCert {
+            dir,
+            cert_path,
+            key_path,
+            client_config: Arc::new(client_config)
+        }
+    }
+
+    fn server_config(&self) -> ServerConfig {
+        ServerConfig::new()
+            .address(LOCALHOST)
+            .tls(&self.cert_path, &self.key_path)
+    }
+
+    async fn connect(&self, local_addr: SocketAddr) -> std::io::Result<futures_rustls::client::TlsStream<TcpStream>> {
+        let tcp_stream = TcpStream::connect(local_addr).await?;
+        let server_name = ServerName::try_from("localhost").unwrap();
+
+        TlsConnector::from(self.client_config.clone())
+            .connect(server_name, tcp_stream)
+            .await
+    }
+}
+
+impl Drop for TestCert {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Waits until the server closes stream, and returns how long that took
+async fn wait_for_close(mut stream: TcpStream) -> Duration {
+    let started = Instant::now();
+    let mut buf = [0u8; 16];
+
+    let read = future::timeout(PROMPTLY, stream.read(&mut buf))
+        .await
+        .expect("The server didn't close the connection");
+
+    // Either a clean close or a reset is fine, as long as nothing was sent
+    if let Ok(read) = read {
+        assert_eq!(read, 0);
+    }
+
+    started.elapsed()
+}
+
+#[async_std::test]
+async fn handshakes_and_echoes() {
+    let cert = TestCert::generate("echo");
+    let mut server = run_server(cert.server_config(), echo).cancel_on_drop();
+    let local_addr = server.ready().await.unwrap()[0];
+
+    let mut stream = cert.connect(local_addr).await.unwrap();
+    stream.write_all(b"hello over tls").await.unwrap();
+    stream.flush().await.unwrap();
+
+    let mut reply = [0u8; 14];
+    future::timeout(PROMPTLY, stream.read_exact(&mut reply)).await.unwrap().unwrap();
+    assert_eq!(&reply, b"hello over tls");
+
+    drop(stream);
+    future::timeout(PROMPTLY, server.shutdown_and_join()).await.unwrap();
+}
+
+// Writes a line and returns, leaving run_server to end the session
+async fn say_hi(stream: ConnectionStream, _cancelable: Cancelable, timeouts: ConnectionTimeouts) -> std::io::Result<()> {
+    let mut writer = &stream;
+    timeouts.write(writer.write_all(b"hi\n")).await
+}
+
+#[async_std::test]
+async fn session_ends_with_close_notify() {
+    let cert = TestCert::generate("close-notify");
+    let mut server = run_server(cert.server_config(), say_hi).cancel_on_drop();
+    let local_addr = server.ready().await.unwrap()[0];
+
+    // Without close_notify, rustls reports the end of the connection as UnexpectedEof
+    let mut stream = cert.connect(local_addr).await.unwrap();
+    let mut received = Vec::new();
+    future::timeout(PROMPTLY, stream.read_to_end(&mut received)).await.unwrap().unwrap();
+    assert_eq!(received, b"hi\n");
+
+    future::timeout(PROMPTLY, server.shutdown_and_join()).await.unwrap();
+}
+
+#[async_std::test]
+async fn handshake_times_out() {
+    let cert = TestCert::generate("timeout");
+    let config = cert.server_config().tls_handshake_timeout(Duration::from_millis(200));
+    let mut server = run_server(config, echo).cancel_on_drop();
+    let local_addr = server.ready().await.unwrap()[0];
+
+    // Connecting without sending a ClientHello leaves the handshake pending
+    let stalled = TcpStream::connect(local_addr).await.unwrap();
+    let waited = wait_for_close(stalled).await;
+    assert!(waited >= Duration::from_millis(150), "Closed after {:?}", waited);
+
+    // The timed out handshake doesn't stop the server from accepting
+    assert!(cert.connect(local_addr).await.is_ok());
+
+    future::timeout(PROMPTLY, server.shutdown_and_join()).await.unwrap();
+}
+
+#[async_std::test]
+async fn shutdown_cancels_pending_handshakes() {
+    let cert = TestCert::generate("shutdown");
+
+    // Neither timeout would end the handshake during the test
+    let config = cert.server_config()
+        .tls_handshake_timeout(Duration::from_secs(60))
+        .drain_timeout(Duration::from_secs(60));
+
+    let mut server = run_server(config, echo).cancel_on_drop();
+    let local_addr = server.ready().await.unwrap()[0];
+
+    let stalled = TcpStream::connect(local_addr).await.unwrap();
+
+    // Wait for the server to start the handshake
+    while server.metrics().active_connections() == 0 {
+        task::sleep(Duration::from_millis(10)).await;
+    }
+
+    server.shutdown();
+    wait_for_close(stalled).await;
+
+    match future::timeout(PROMPTLY, server.join()).await.unwrap() {
+        ServerOutcome::Drained(stats) => assert_eq!((stats.drained, stats.aborted), (1, 0)),
+        outcome => panic!("Unexpected outcome: {:?}", outcome)
+    }
+}
+
+#[async_std::test]
+async fn invalid_certificate_fails_ready() {
+    let cert = TestCert::generate("invalid");
+    fs::write(&cert.cert_path, "not a certificate").unwrap();
+
+    let mut server = run_server(cert.server_config(), echo);
+    let err = server.ready().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+
+    match server.join().await {
+        ServerOutcome::Failed(err) => assert_eq!(err.kind(), ErrorKind::InvalidData),
+        outcome => panic!("Unexpected outcome: {:?}", outcome)
+    }
+}
+
+#[async_std::test]
+async fn mismatched_key_fails_ready() {
+    let cert = TestCert::generate("mismatched");
+    let other = TestCert::generate("mismatched-other");
+
+    let config = ServerConfig::new()
+        .address(LOCALHOST)
+        .tls(&cert.cert_path, &other.key_path);
+
+    let err = run_server(config, echo).ready().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+}
+
+#[async_std::test]
+async fn missing_key_fails_ready() {
+    let cert = TestCert::generate("missing");
+    fs::remove_file(&cert.key_path).unwrap();
+
+    let err = run_server(cert.server_config(), echo).ready().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound, "{}", err);
+}